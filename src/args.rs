@@ -1,8 +1,18 @@
+use std::env;
+use std::fs;
+use std::io;
 use std::path::PathBuf;
-use clap::Parser;
+use clap::{ArgGroup, Args, Parser, ValueEnum};
 
-use crate::chunk_type::ChunkType;
+use pngme::compression::Algorithm;
+use pngme::idat::IDAT_CHUNK_SIZE;
+use pngme::lsb::Channels;
+use pngme::operations::Selection;
+use pngme::pixels::PixelFormat;
+use pngme::png::Position;
+use pngme::seal::SealHash;
 
+use crate::output::Format;
 
 #[derive(Parser, Debug)]
 #[clap(name = "pngme")]
@@ -11,30 +21,291 @@ pub enum PngMeArgs {
     Decode(DecodeArgs),
     Remove(RemoveArgs),
     Print(PrintArgs),
+    Verify(VerifyArgs),
+    Seal(SealArgs),
+    CheckSeal(CheckSealArgs),
+    Keygen(KeygenArgs),
+    Capacity(CapacityArgs),
+    Interlace(InterlaceArgs),
+    Deinterlace(InterlaceArgs),
+    Recompress(RecompressArgs),
+    ExportPixels(ExportPixelsArgs),
 }
 
 #[derive(Parser, Debug)]
+#[command(group(ArgGroup::new("source").required(true).args(["message", "file", "stdin"])))]
 pub struct EncodeArgs {
     pub image_path: PathBuf,
     pub chunk_type: String,
-    pub message: String,
+    /// The message to hide; use --file or --stdin for binary data
+    pub message: Option<String>,
+    #[arg(requires = "message")]
     pub output_file_path: Option<PathBuf>,
+    /// Write the result here instead of overwriting the input; the only way to name
+    /// the output with --file or --stdin
+    #[arg(short, long, conflicts_with = "output_file_path")]
+    pub output: Option<PathBuf>,
+    /// Hide the contents of this file, along with its name, MIME type and size
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+    /// Hide the bytes read from standard input
+    #[arg(long)]
+    pub stdin: bool,
+    /// MIME type stored with --file or --stdin, guessed from the file name by default
+    #[arg(long)]
+    pub mime_type: Option<String>,
+    /// Where to insert the chunk: before-iend (the default), after-ihdr, before-idat or an
+    /// index from 1 up to that of IEND
+    #[arg(long)]
+    pub position: Option<Position>,
+    /// Encrypt the message with a key derived from a password. In lsb mode the password
+    /// also scatters the hidden bits over the image.
+    #[command(flatten)]
+    pub password: PasswordArgs,
+    /// Encrypt the message for the public key in this file (see `pngme keygen`)
+    #[arg(long, value_name = "PUBKEY_FILE", conflicts_with = "password_source")]
+    pub recipient: Option<PathBuf>,
+    /// Sign the chunk with the Ed25519 key in this file (see `pngme keygen --signing`)
+    #[arg(long, value_name = "KEYFILE")]
+    pub sign: Option<PathBuf>,
+    /// Make the signature cover the image data too, so it breaks if the pixels change
+    #[arg(long, requires = "sign")]
+    pub sign_idat: bool,
+    /// Compress the message with deflate, zstd or brotli before storing it
+    #[arg(long, value_name = "ALGORITHM")]
+    pub compress: Option<Algorithm>,
+    /// Split the message across N chunks of the same type, inserted together
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub parts: Option<u32>,
+    /// Store the message in a chunk, or in the low bits of the pixels
+    #[arg(long, value_enum, default_value_t = Mode::Chunk)]
+    pub mode: Mode,
+    /// Channels whose low bits carry the message in lsb mode, as letters from rgba
+    #[arg(long, default_value = "rgb")]
+    pub channels: Channels,
 }
 
 #[derive(Parser, Debug)]
 pub struct DecodeArgs {
     pub image_path: PathBuf,
     pub chunk_type: String,
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+    /// Password of encrypted messages, and of scattered ones in lsb mode
+    #[command(flatten)]
+    pub password: PasswordArgs,
+    /// Secret key file for messages encrypted to a public key
+    #[arg(long, value_name = "KEYFILE")]
+    pub identity: Option<PathBuf>,
+    /// Write the raw message to this file, or into this directory under its stored name
+    #[arg(long, conflicts_with = "all")]
+    pub out: Option<PathBuf>,
+    /// Refuse to decompress messages larger than this many bytes
+    #[arg(long, value_name = "BYTES")]
+    pub max_size: Option<u64>,
+    /// Read the message from a chunk, or from the low bits of the pixels
+    #[arg(long, value_enum, default_value_t = Mode::Chunk)]
+    pub mode: Mode,
+    /// Channels the message was hidden in with `encode --mode lsb`
+    #[arg(long, default_value = "rgb")]
+    pub channels: Channels,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
 }
 
 #[derive(Parser, Debug)]
 pub struct RemoveArgs {
     pub image_path: PathBuf,
     pub chunk_type: String,
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+    /// Write the result here instead of overwriting the input
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
 }
 
 #[derive(Parser, Debug)]
 pub struct PrintArgs {
     pub image_path: PathBuf,
+    /// Print every chunk in full instead of a table
+    #[arg(short, long)]
+    pub verbose: bool,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
 }
 
+/// Checks the signature of every custom chunk against a trusted public key
+#[derive(Parser, Debug)]
+pub struct VerifyArgs {
+    pub image_path: PathBuf,
+    /// Public key file of the trusted signer
+    #[arg(long, value_name = "PUBKEY_FILE")]
+    pub key: PathBuf,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+}
+
+/// Signs a digest of IHDR, PLTE and IDAT into a seal chunk, replacing any previous seal
+#[derive(Parser, Debug)]
+pub struct SealArgs {
+    pub image_path: PathBuf,
+    /// Ed25519 signing key file (see `pngme keygen --signing`)
+    #[arg(long, value_name = "KEYFILE")]
+    pub key: PathBuf,
+    /// Hash function for the digests: sha256 or blake3
+    #[arg(long, default_value = "sha256")]
+    pub hash: SealHash,
+    /// Write the result here instead of overwriting the input
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+/// Checks that the image still matches its seal
+#[derive(Parser, Debug)]
+pub struct CheckSealArgs {
+    pub image_path: PathBuf,
+    /// Public key file of the trusted signer
+    #[arg(long, value_name = "PUBKEY_FILE")]
+    pub key: PathBuf,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+}
+
+/// Generates a key pair: the secret key is written to OUTPUT, the public key to OUTPUT.pub
+#[derive(Parser, Debug)]
+pub struct KeygenArgs {
+    pub output: PathBuf,
+    /// Generate an Ed25519 signing key instead of an X25519 encryption key
+    #[arg(long)]
+    pub signing: bool,
+}
+
+/// Reports how much data the pixels of an image can hide, and the per-chunk limit
+#[derive(Parser, Debug)]
+pub struct CapacityArgs {
+    pub image_path: PathBuf,
+    /// Channels whose low bits would carry data, as letters from rgba
+    #[arg(long, default_value = "rgb")]
+    pub channels: Channels,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+}
+
+/// Re-encodes the pixels with Adam7 interlacing (`interlace`) or without it
+/// (`deinterlace`), rewriting IHDR to match
+#[derive(Parser, Debug)]
+pub struct InterlaceArgs {
+    pub image_path: PathBuf,
+    /// Write the result here instead of overwriting the input
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+/// Deflates the image data again and re-splits it into IDAT chunks; other chunks are
+/// left untouched
+#[derive(Parser, Debug)]
+pub struct RecompressArgs {
+    pub image_path: PathBuf,
+    /// zlib level, from 0 (no compression) to 9 (smallest)
+    #[arg(long, default_value_t = 9, value_parser = clap::value_parser!(u32).range(0..=9))]
+    pub level: u32,
+    /// Largest IDAT chunk to write, in bytes
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = IDAT_CHUNK_SIZE as u32,
+        value_parser = clap::value_parser!(u32).range(1..=i32::MAX as i64)
+    )]
+    pub chunk_size: u32,
+    /// Write the result here instead of overwriting the input
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+}
+
+/// Writes the decoded pixels, with palettes expanded and tRNS as alpha
+#[derive(Parser, Debug)]
+pub struct ExportPixelsArgs {
+    pub image_path: PathBuf,
+    pub output: PathBuf,
+    /// raw (RGBA), pnm (PGM or PPM, without alpha) or pam; guessed from the output
+    /// extension by default
+    #[arg(long, value_name = "FORMAT")]
+    pub to: Option<PixelFormat>,
+}
+
+/// Where a message is stored.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// In a chunk of the given type
+    Chunk,
+    /// In the least significant bits of the pixels, labelled with the chunk type. The
+    /// image data is re-encoded, so this survives tools that strip ancillary chunks. With
+    /// a password the bits are scattered over the image rather than filled in order.
+    Lsb,
+}
+
+// Picks which chunks of the requested type to act on; the first one by default. Not a
+// doc comment, or clap would show it as the help of the subcommands that flatten this.
+#[derive(Args, Debug)]
+#[group(multiple = false)]
+pub struct SelectionArgs {
+    /// Every chunk of that type
+    #[arg(long)]
+    pub all: bool,
+    /// The N-th chunk of that type, counting from 0
+    #[arg(long, value_name = "N")]
+    pub index: Option<usize>,
+    /// The last chunk of that type
+    #[arg(long)]
+    pub last: bool,
+}
+
+impl SelectionArgs {
+    pub fn selection(&self) -> Selection {
+        match (self.all, self.index, self.last) {
+            (true, _, _) => Selection::All,
+            (_, Some(n), _) => Selection::Nth(n),
+            (_, _, true) => Selection::Last,
+            _ => Selection::First,
+        }
+    }
+}
+
+// Where the password comes from. Command line arguments can be seen by other users, so
+// --password-file and --password-env are the safer choices. Not a doc comment: clap would
+// use it as the help of every subcommand that flattens this.
+#[derive(Args, Debug)]
+#[group(id = "password_source", multiple = false)]
+pub struct PasswordArgs {
+    /// The password itself; it shows up in the process list and shell history
+    #[arg(long)]
+    pub password: Option<String>,
+    /// Read the password from the first line of this file
+    #[arg(long, value_name = "FILE")]
+    pub password_file: Option<PathBuf>,
+    /// Read the password from this environment variable
+    #[arg(long, value_name = "VAR")]
+    pub password_env: Option<String>,
+}
+
+impl PasswordArgs {
+    pub fn password(&self) -> io::Result<Option<String>> {
+        if let Some(path) = &self.password_file {
+            let contents = fs::read_to_string(path)?;
+            return Ok(Some(contents.lines().next().unwrap_or_default().to_string()));
+        }
+        if let Some(name) = &self.password_env {
+            return env::var(name).map(Some).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("environment variable {} is not set or not valid UTF-8", name),
+                )
+            });
+        }
+        Ok(self.password.clone())
+    }
+}
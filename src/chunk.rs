@@ -1,85 +1,62 @@
 use std::fmt;
-use std::{convert::TryFrom, str::from_utf8};
+use std::convert::TryFrom;
 use std::io::{BufReader, Read};
-use crate::{chunk_type, Error, Result};
+use crate::{Error, Result};
 use crate::chunk_type::ChunkType;
 use crc::crc32;
 
-const MAXIMUM_LENGTH: u32 = (1 << 31) - 1;
-
-// Errors implementation
-#[derive(Debug)]
-pub struct ChunkError {
-    message: String,
-}
-
-impl ChunkError {
-    fn boxed(message: String) -> Box<Self> {
-        Box::new(Self {message})
-    }
-}
-
-impl fmt::Display for ChunkError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Bad chunk: {}", self.message)
-    }
-}
-
-impl std::error::Error for ChunkError {}
+/// The largest data length a chunk may declare, 2^31 - 1 bytes.
+pub const MAXIMUM_LENGTH: u32 = (1 << 31) - 1;
 
 #[derive(Debug, Clone)]
 pub struct Chunk {
-    pub length: u32,
-    pub chunk_type: ChunkType,
-    pub data: Vec<u8>,
-    pub crc: u32,
+    length: u32,
+    chunk_type: ChunkType,
+    data: Vec<u8>,
+    crc: u32,
 }
 
 impl TryFrom<&[u8]> for Chunk {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self> {
+        let truncated = |_| Error::TruncatedChunk { offset: 0 };
         let mut reader = BufReader::new(value);
         let mut buf: [u8; 4] = [0; 4];
-        reader.read_exact(&mut buf)?;
+        reader.read_exact(&mut buf).map_err(truncated)?;
         
         // Length
         let length = u32::from_be_bytes(buf);
         //Error handling
         if length > MAXIMUM_LENGTH {
-            return Err(ChunkError::boxed(format!(
-                "Length is too long ({} > 2^31 - 1)",
-                length
-            )));
+            return Err(Error::ChunkTooLong { offset: 0, length });
         }
 
         // Chunk Type
-        reader.read_exact(&mut buf);
+        reader.read_exact(&mut buf).map_err(truncated)?;
         let chunk_type: ChunkType = ChunkType::try_from(buf)?;
         
         // Data
-        let mut chunk_data: Vec<u8> = vec![0; usize::try_from(length)?];
-        reader.read_exact(&mut chunk_data);
-        if chunk_data.len() != length.try_into()? {
-            return Err(ChunkError::boxed(format!(
-                "Data has invalid length. Expected {}, but got {}.",
-                length, chunk_data.len()
-            )));
+        let mut chunk_data: Vec<u8> = Vec::new();
+        (&mut reader).take(length.into()).read_to_end(&mut chunk_data)?;
+        if chunk_data.len() < length as usize {
+            return Err(Error::TruncatedChunk { offset: 0 });
         }
 
-        reader.read_exact(&mut buf)?;
+        reader.read_exact(&mut buf).map_err(truncated)?;
         let provided_crc = u32::from_be_bytes(buf);
-        let actual_crc = crc::crc32::checksum_ieee(&[&chunk_type.bytes(), chunk_data. as_slice()].concat());
+        let actual_crc = crc::crc32::checksum_ieee(&[&chunk_type.bytes(), chunk_data.as_slice()].concat());
         if provided_crc != actual_crc {
-            return Err(ChunkError::boxed(format!(
-                "Invalid crc. Expected {}, but got {}.",
-                actual_crc, provided_crc
-            )));
+            return Err(Error::CrcMismatch {
+                offset: 0,
+                expected: actual_crc,
+                actual: provided_crc,
+            });
         }
 
         Ok(Chunk {
-            length: length,
-            chunk_type: chunk_type,
+            length,
+            chunk_type,
             data: chunk_data,
             crc: provided_crc,
         })
@@ -103,9 +80,9 @@ impl Chunk {
         let crc = crc32::checksum_ieee(&[&chunk_type.bytes(), data.as_slice()].concat());
         Chunk {
             length: data.len() as u32,
-            chunk_type: chunk_type,
-            data: data,
-            crc: crc,
+            chunk_type,
+            data,
+            crc,
         }
     }
     pub fn length(&self) -> u32 {
@@ -121,13 +98,13 @@ impl Chunk {
         self.crc
     }
     pub fn data_as_string(&self) -> Result<String> {
-        Ok(String::from_utf8(self.data.clone()).unwrap())
+        Ok(String::from_utf8(self.data.clone())?)
     }
     pub fn as_bytes(&self) -> Vec<u8> {
         self.length
             .to_be_bytes()
             .iter()
-            .chain(self.chunk_type.arr.iter())
+            .chain(self.chunk_type.bytes().iter())
             .chain(self.data.iter())
             .chain(self.crc.to_be_bytes().iter())
             .copied()
@@ -242,6 +219,17 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_truncated_huge_chunk_from_bytes() {
+        let mut bytes = 0x7fff_ffffu32.to_be_bytes().to_vec();
+        bytes.extend_from_slice(b"ruSt");
+        bytes.extend_from_slice(&[0; 400]);
+        assert!(matches!(
+            Chunk::try_from(bytes.as_slice()),
+            Err(Error::TruncatedChunk { .. })
+        ));
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -6,43 +6,26 @@ use crate::{Error, Result};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChunkType {
-    pub arr: [u8; 4],
+    arr: [u8; 4],
 }
 
 impl TryFrom<[u8; 4]> for ChunkType {
     type Error = Error;
 
     fn try_from(value: [u8; 4]) -> Result<Self> {
+        if !value.iter().all(|&byte| Self::is_valid_byte(byte)) {
+            return Err(Error::InvalidChunkType { chunk_type: value.escape_ascii().to_string() });
+        }
         Ok(ChunkType { arr: value })
     }
 }
 
-// Errors implementation
-#[derive(Debug)]
-pub struct ChunkTypeError {
-    message: String,
-}
-
-impl ChunkTypeError {
-    fn boxed(message: String) -> Box<Self> {
-        Box::new(Self {message})
-    }
-}
-
-impl fmt::Display for ChunkTypeError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Bad chunk type: {}", self.message)
-    }
-}
-
-impl std::error::Error for ChunkTypeError {}
-
 impl FromStr for ChunkType {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self> {
         if s.len() != 4 {
-            return Err(ChunkTypeError::boxed(format!("Chunk Type should have 4 bytes, but got {}", s.len())));
+            return Err(Error::InvalidChunkType { chunk_type: s.to_string() });
         }
 
         let mut arr = [0u8; 4];
@@ -50,7 +33,7 @@ impl FromStr for ChunkType {
             if Self::is_valid_byte(*byte) {
                 arr[i] = *byte;
             } else {
-                return Err(ChunkTypeError::boxed(format!("Bad chunk byte {}", byte)));
+                return Err(Error::InvalidChunkType { chunk_type: s.to_string() });
             }
         }
         Ok(ChunkType { arr })            
@@ -83,11 +66,23 @@ impl ChunkType {
     pub fn is_safe_to_copy(&self) -> bool {
         self.arr[3].is_ascii_lowercase()
     }
+    /// Chunk types are made of ASCII letters only.
     pub fn is_valid_byte(byte: u8) -> bool {
-        byte.is_ascii()
+        byte.is_ascii_alphabetic()
+    }
+    /// Whether the type is defined by the PNG specification or its registered extensions,
+    /// as opposed to a custom chunk added by an application.
+    pub fn is_standard(&self) -> bool {
+        STANDARD_TYPES.contains(&&self.arr)
     }
 }
 
+const STANDARD_TYPES: [&[u8; 4]; 30] = [
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"tRNS", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB",
+    b"cICP", b"mDCV", b"cLLI", b"tEXt", b"zTXt", b"iTXt", b"bKGD", b"hIST", b"pHYs", b"sPLT",
+    b"eXIf", b"tIME", b"acTL", b"fcTL", b"fdAT", b"oFFs", b"pCAL", b"sCAL", b"sTER", b"dSIG",
+];
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -102,6 +97,24 @@ mod tests {
         assert_eq!(expected, actual.bytes());
     }
 
+    #[test]
+    pub fn test_chunk_type_from_invalid_bytes() {
+        for bytes in [[0xff, 0xfe, 0xfd, 0xfc], *b"Ru1t", *b"Ru t"] {
+            assert!(matches!(
+                ChunkType::try_from(bytes),
+                Err(Error::InvalidChunkType { .. })
+            ));
+        }
+        assert!(ChunkType::from_str("Ru1t").is_err());
+    }
+
+    #[test]
+    pub fn test_chunk_type_is_standard() {
+        assert!(ChunkType::from_str("IDAT").unwrap().is_standard());
+        assert!(ChunkType::from_str("tEXt").unwrap().is_standard());
+        assert!(!ChunkType::from_str("RuSt").unwrap().is_standard());
+    }
+
     #[test]
     pub fn test_chunk_type_from_str() {
         let expected = ChunkType::try_from([82, 117, 83, 116]).unwrap();
@@ -1,38 +1,643 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::path::PathBuf;
 
-use crate::args::{EncodeArgs, DecodeArgs, RemoveArgs, PrintArgs};
-use crate::Result;
-use crate::png::Png;
-use crate::chunk_type::ChunkType;
-use crate::chunk::Chunk;
+use clap::error::ErrorKind;
+use clap::CommandFactory;
+use pngme::chunk::MAXIMUM_LENGTH;
+use pngme::envelope::{self, Metadata, OpenOptions, SealOptions, Verification};
+use pngme::idat::{self, Scanlines};
+use pngme::ihdr::{Ihdr, Interlace};
+use pngme::keys::{Identity, Recipient, SigningKey, VerifyingKey};
+use pngme::lsb::{self, ScatterKey};
+use pngme::operations;
+use pngme::parts;
+use pngme::pixels::{self, PixelFormat};
+use pngme::png::{Png, Position};
+use pngme::reader::{ChunkReader, StreamedChunk};
+use pngme::seal;
+use pngme::{Chunk, ChunkType, Error, Result};
+
+use crate::args::{
+    CapacityArgs, CheckSealArgs, DecodeArgs, EncodeArgs, ExportPixelsArgs, InterlaceArgs, KeygenArgs, Mode,
+    PrintArgs, RecompressArgs, RemoveArgs, SealArgs, VerifyArgs,
+};
+use crate::output::{
+    self, CapacityJson, CapacityLevelJson, ChunkJson, CheckSealJson, DecodeJson, Format,
+    ImageJson, PixelsJson, PrintJson, RecompressJson, RemoveJson, SignatureJson, SizeJson,
+    VerifyJson,
+};
 
 pub fn encode(args: EncodeArgs) -> Result<()> {
-    let output_file_path = args.output_file_path.unwrap_or_else(|| {
-        args.image_path.clone()
-    });
-    let mut png: Png = Png::from_file(args.image_path)?;
-    let chunk_type: ChunkType = ChunkType::from_str(&args.chunk_type).unwrap();
-    let chunk: Chunk = Chunk::new(chunk_type, args.message.as_bytes().to_vec());
-    png.append_chunk(chunk);
-    png.write_file(output_file_path)?;
-    Ok(())
+    if args.mode == Mode::Lsb {
+        reject_in_lsb_mode::<EncodeArgs>(&[
+            ("--parts", args.parts.is_some()),
+            ("--sign-idat", args.sign_idat),
+            ("--position", args.position.is_some()),
+        ]);
+    }
+    let output_file_path = args
+        .output
+        .or(args.output_file_path)
+        .unwrap_or_else(|| args.image_path.clone());
+    let (message, metadata) = if let Some(path) = &args.file {
+        let data = fs::read(path)?;
+        let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+        let mime_type = args
+            .mime_type
+            .clone()
+            .or_else(|| file_name.as_deref().map(guess_mime_type));
+        let metadata = Metadata { file_name, mime_type, size: data.len() as u64 };
+        (data, Some(metadata))
+    } else if args.stdin {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data)?;
+        let metadata = Metadata {
+            file_name: None,
+            mime_type: args.mime_type.clone(),
+            size: data.len() as u64,
+        };
+        (data, Some(metadata))
+    } else {
+        (args.message.clone().unwrap_or_default().into_bytes(), None)
+    };
+    let recipient = match &args.recipient {
+        Some(path) => Some(Recipient::from_str(&fs::read_to_string(path)?)?),
+        None => None,
+    };
+    let password = args.password.password()?;
+    let options = SealOptions {
+        password: password.as_deref(),
+        recipient: recipient.as_ref(),
+        metadata: metadata.as_ref(),
+        compression: args.compress,
+        ..Default::default()
+    };
+    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+    let data = envelope::seal(&message, &options)?;
+    // Split first, then sign every part, so each chunk carries its own signature.
+    let mut pieces = match args.parts {
+        Some(count) => parts::split(&data, count)?,
+        None => vec![data],
+    };
+    if let Some(path) = &args.sign {
+        let key = SigningKey::from_str(&fs::read_to_string(path)?)?;
+        let digest = match args.sign_idat {
+            true => Some(operations::idat_digest(BufReader::new(File::open(&args.image_path)?))?),
+            false => None,
+        };
+        for piece in &mut pieces {
+            *piece = envelope::sign(piece, &chunk_type, &key, digest.as_ref())?;
+        }
+    }
+    if args.mode == Mode::Lsb {
+        let mut png = Png::from_file(&args.image_path)?;
+        let key = scatter_key(password.as_deref())?;
+        lsb::embed(&mut png, &chunk_type, &pieces[0], args.channels, key.as_ref())?;
+        return write_atomically(&output_file_path, |mut output| {
+            output.write_all(&png.as_bytes())?;
+            output.flush()?;
+            Ok(())
+        });
+    }
+    let chunks = pieces
+        .into_iter()
+        .map(|piece| Chunk::new(chunk_type.clone(), piece))
+        .collect();
+    let input = BufReader::new(File::open(&args.image_path)?);
+    write_atomically(&output_file_path, |output| {
+        let position = args.position.unwrap_or(Position::BeforeIend);
+        operations::insert_stream(input, output, chunks, position)?;
+        Ok(())
+    })
 }
 pub fn decode(args: DecodeArgs) -> Result<()> {
-    let png: Png = Png::from_file(args.image_path)?;
-    let chunk = png.chunk_by_type(&args.chunk_type).unwrap();
-    println!("Chunk data: {}", chunk.data_as_string()?);
+    if args.mode == Mode::Lsb {
+        reject_in_lsb_mode::<DecodeArgs>(&[
+            ("--all", args.selection.all),
+            ("--index", args.selection.index.is_some()),
+            ("--last", args.selection.last),
+        ]);
+    }
+    let password = args.password.password()?;
+    let selected: Vec<(Option<StreamedChunk>, Vec<u8>)> = match args.mode {
+        Mode::Chunk => {
+            let file = BufReader::new(File::open(&args.image_path)?);
+            operations::messages_from_reader(file, &args.chunk_type, args.selection.selection())?
+                .into_iter()
+                .map(|(streamed, data)| (Some(streamed), data))
+                .collect()
+        }
+        Mode::Lsb => {
+            let png = Png::from_file(&args.image_path)?;
+            let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+            let key = scatter_key(password.as_deref())?;
+            vec![(None, lsb::extract(&png, &chunk_type, args.channels, key.as_ref())?)]
+        }
+    };
+    let identity = match &args.identity {
+        Some(path) => Some(Identity::from_str(&fs::read_to_string(path)?)?),
+        None => None,
+    };
+    let options = OpenOptions {
+        password: password.as_deref(),
+        identity: identity.as_ref(),
+        decompression_limit: args.max_size,
+    };
+    let mut chunks = Vec::new();
+    let mut pixels = None;
+    for (streamed, data) in &selected {
+        let message = envelope::open(data, &options)?;
+        if let Some(metadata) = &message.metadata {
+            if metadata.size != message.data.len() as u64 {
+                return Err(Error::InvalidEnvelope {
+                    reason: format!(
+                        "stored size is {} bytes, but the message has {}",
+                        metadata.size,
+                        message.data.len()
+                    ),
+                });
+            }
+        }
+        let written = match &args.out {
+            Some(out) => {
+                let path = output_path(out, message.metadata.as_ref())?;
+                fs::write(&path, &message.data)?;
+                Some(path)
+            }
+            None => None,
+        };
+        if args.format == Format::Json {
+            match streamed {
+                Some(streamed) => chunks.push(ChunkJson::from(streamed).with_message(&message)),
+                None => pixels = Some(PixelsJson::new(&args.chunk_type, args.channels, &message)),
+            }
+            continue;
+        }
+        if let Some(metadata) = &message.metadata {
+            println!(
+                "File: {}, {}, {} bytes",
+                metadata.file_name.as_deref().unwrap_or("(unnamed)"),
+                metadata.mime_type.as_deref().unwrap_or("unknown type"),
+                metadata.size
+            );
+        }
+        match (written, std::str::from_utf8(&message.data)) {
+            (Some(path), _) => println!("Wrote {} bytes to {}", message.data.len(), path.display()),
+            (None, Ok(text)) => println!("Chunk data: {}", text),
+            (None, Err(_)) => println!(
+                "Chunk data: {} bytes of binary data, use --out to save them",
+                message.data.len()
+            ),
+        }
+    }
+    if args.format == Format::Json {
+        output::print_json(&DecodeJson { chunks, pixels });
+    }
     Ok(())
 }
 pub fn remove(args: RemoveArgs) -> Result<()> {
-    let output_file_path = args.image_path.clone();
+    let output_file_path = args.output.unwrap_or_else(|| args.image_path.clone());
     let mut png: Png = Png::from_file(args.image_path)?;
-    png.remove_chunk(&args.chunk_type);
-    png.write_file(output_file_path)?;
+    let selection = args.selection.selection();
+    let indices = operations::select(&png, &args.chunk_type, selection)?;
+    let removed = chunks_json(&png, &indices);
+    operations::remove_selected(&mut png, &args.chunk_type, selection)?;
+    png.write_file(&output_file_path)?;
+    if args.format == Format::Json {
+        output::print_json(&RemoveJson {
+            output: output_file_path.display().to_string(),
+            removed,
+        });
+    } else {
+        println!("Removed {} chunk(s) of type {}", indices.len(), args.chunk_type);
+    }
     Ok(())
 }
 pub fn print(args: PrintArgs) -> Result<()> {
-    let png: Png = Png::from_file(args.image_path)?;
-    println!("{:?}", png.as_bytes());
+    let file = BufReader::new(File::open(args.image_path)?);
+    let mut chunks = ChunkReader::new(file)?;
+    // Pixel data is never shown, so there is no need to read it.
+    if !args.verbose {
+        chunks = chunks.skip_data(|chunk_type| &chunk_type.bytes() == b"IDAT");
+    }
+    let mut chunks = chunks.peekable();
+    let header = match chunks.peek() {
+        Some(Ok(first)) => first.chunk().map(Ihdr::try_from),
+        _ => None,
+    }
+    .unwrap_or_else(|| Err(Error::ChunkNotFound { chunk_type: "IHDR".to_string() }));
+
+    if args.format == Format::Json {
+        let chunks = chunks.collect::<Result<Vec<StreamedChunk>>>()?;
+        output::print_json(&PrintJson {
+            image: header.ok().as_ref().map(ImageJson::from),
+            size: file_size(chunks.last()),
+            chunks: chunks.iter().map(ChunkJson::from).collect(),
+        });
+        return Ok(());
+    }
+    match header {
+        Ok(ihdr) => println!("Image: {}", ihdr),
+        Err(e) => println!("Image: unknown ({})", e),
+    }
+    println!();
+
+    let mut last = None;
+    if args.verbose {
+        for chunk in chunks {
+            let chunk = chunk?;
+            if let Some(full) = chunk.chunk() {
+                print!("{}", full);
+            }
+            last = Some(chunk);
+        }
+    } else {
+        println!(
+            "{:>5}  {:>10}  {:<4}  {:>10}  {:<10}  {:<8}  {:<6}  Safe-to-copy",
+            "Index", "Offset", "Type", "Length", "CRC", "Critical", "Public"
+        );
+        for chunk in chunks {
+            let chunk = chunk?;
+            let chunk_type = chunk.chunk_type();
+            println!(
+                "{:>5}  {:>10}  {:<4}  {:>10}  {:#010x}  {:<8}  {:<6}  {}",
+                chunk.index(),
+                chunk.offset(),
+                chunk_type,
+                chunk.length(),
+                chunk.crc(),
+                yes_no(chunk_type.is_critical()),
+                yes_no(chunk_type.is_public()),
+                yes_no(chunk_type.is_safe_to_copy()),
+            );
+            last = Some(chunk);
+        }
+    }
+
+    println!();
+    println!(
+        "Chunks: {}, {} bytes",
+        last.as_ref().map_or(0, |chunk| chunk.index() + 1),
+        file_size(last.as_ref())
+    );
+    Ok(())
+}
+pub fn verify(args: VerifyArgs) -> Result<()> {
+    let key = VerifyingKey::from_str(&fs::read_to_string(&args.key)?)?;
+    let digest = operations::idat_digest(BufReader::new(File::open(&args.image_path)?))?;
+    let file = BufReader::new(File::open(&args.image_path)?);
+    let verified = operations::verify_stream(file, &key, &digest)?;
+
+    if args.format == Format::Json {
+        output::print_json(&VerifyJson {
+            chunks: verified.iter().map(SignatureJson::from).collect(),
+        });
+    } else if verified.is_empty() {
+        println!("No custom chunks found");
+    } else {
+        println!("{:>5}  {:>10}  {:<4}  Signature", "Index", "Offset", "Type");
+        for (chunk, verification) in &verified {
+            println!(
+                "{:>5}  {:>10}  {:<4}  {}",
+                chunk.index(),
+                chunk.offset(),
+                chunk.chunk_type(),
+                verification
+            );
+        }
+    }
+
+    let invalid = verified
+        .iter()
+        .filter(|(_, verification)| *verification == Verification::Invalid)
+        .count();
+    if invalid > 0 {
+        return Err(Error::InvalidSignature { count: invalid });
+    }
+    Ok(())
+}
+
+pub fn seal(args: SealArgs) -> Result<()> {
+    let output_file_path = args.output.unwrap_or_else(|| args.image_path.clone());
+    let key = SigningKey::from_str(&fs::read_to_string(&args.key)?)?;
+    let mut png = Png::from_file(&args.image_path)?;
+    seal::seal(&mut png, args.hash, &key)?;
+    png.write_file(&output_file_path)?;
+    println!("Sealed {} with {}", output_file_path.display(), args.hash);
+    Ok(())
+}
+
+pub fn check_seal(args: CheckSealArgs) -> Result<()> {
+    let key = VerifyingKey::from_str(&fs::read_to_string(&args.key)?)?;
+    let png = Png::from_file(&args.image_path)?;
+    let report = seal::check(&png, &key)?;
+
+    if args.format == Format::Json {
+        output::print_json(&CheckSealJson::from(&report));
+    } else {
+        let signature = if report.signature_valid { "valid" } else { "invalid" };
+        println!("Seal: {}, signature {}", report.hash, signature);
+        for (chunk_type, status) in &report.digests {
+            println!("  {}  {}", chunk_type, status);
+        }
+    }
+
+    if !report.signature_valid {
+        return Err(Error::InvalidSeal {
+            reason: "it was not signed by the trusted key".to_string(),
+        });
+    }
+    if !report.is_intact() {
+        return Err(Error::InvalidSeal {
+            reason: "the image was modified after sealing".to_string(),
+        });
+    }
+    Ok(())
+}
+
+pub fn keygen(args: KeygenArgs) -> Result<()> {
+    let (secret, public) = if args.signing {
+        let key = SigningKey::generate();
+        (key.to_string(), key.verifying_key().to_string())
+    } else {
+        let identity = Identity::generate();
+        (identity.to_string(), identity.recipient().to_string())
+    };
+    let mut public_path = args.output.as_os_str().to_owned();
+    public_path.push(".pub");
+
+    // Never overwrite an existing key, and keep the secret one private to the user.
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    // Create both files before writing either, so a failure leaves no half key pair.
+    let mut secret_file = options.open(&args.output)?;
+    let mut public_file = match File::options().write(true).create_new(true).open(&public_path) {
+        Ok(file) => file,
+        Err(e) => {
+            let _ = fs::remove_file(&args.output);
+            return Err(e.into());
+        }
+    };
+    let written = writeln!(secret_file, "# public key: {}", public)
+        .and_then(|()| writeln!(secret_file, "{}", secret))
+        .and_then(|()| writeln!(public_file, "{}", public));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&args.output);
+        let _ = fs::remove_file(&public_path);
+        return Err(e.into());
+    }
+
+    println!("Public key: {}", public);
     Ok(())
-}
\ No newline at end of file
+}
+pub fn capacity(args: CapacityArgs) -> Result<()> {
+    let png = Png::from_file(&args.image_path)?;
+    let ihdr = png.header_info()?;
+    let levels: Vec<CapacityLevelJson> = (1..=lsb::MAX_BITS_PER_SAMPLE)
+        .map(|bits| CapacityLevelJson {
+            bits_per_channel: bits,
+            bits_per_pixel: lsb::bits_per_pixel(&ihdr, args.channels, bits),
+            bytes: lsb::theoretical_capacity(&ihdr, args.channels, bits),
+        })
+        .collect();
+
+    if args.format == Format::Json {
+        output::print_json(&CapacityJson {
+            image: ImageJson::from(&ihdr),
+            channels: args.channels.to_string(),
+            levels,
+            chunk_limit: MAXIMUM_LENGTH,
+        });
+        return Ok(());
+    }
+    println!("Image: {}", ihdr);
+    println!("Channels: {}", args.channels);
+    println!();
+    println!("{:>12}  {:>10}  Capacity", "Bits/channel", "Bits/pixel");
+    for level in &levels {
+        match (level.bits_per_pixel, level.bytes) {
+            (Some(bits_per_pixel), Some(bytes)) => println!(
+                "{:>12}  {:>10}  {} bytes",
+                level.bits_per_channel, bits_per_pixel, bytes
+            ),
+            _ => println!("{:>12}  {:>10}  -", level.bits_per_channel, "-"),
+        }
+    }
+    println!();
+    match lsb::capacity(&ihdr, args.channels) {
+        Ok(bytes) => println!("encode --mode lsb (1 bit/channel): {} bytes", bytes),
+        Err(e) => println!("encode --mode lsb: unavailable ({})", e),
+    }
+    println!("Chunk limit: {} bytes per chunk", MAXIMUM_LENGTH);
+    Ok(())
+}
+
+pub fn interlace(args: InterlaceArgs, interlace: Interlace) -> Result<()> {
+    let output_file_path = args.output.unwrap_or_else(|| args.image_path.clone());
+    let mut png = Png::from_file(&args.image_path)?;
+    let mut scanlines = Scanlines::read(&png)?;
+    if scanlines.ihdr().interlace == interlace {
+        if output_file_path != args.image_path {
+            write_atomically(&output_file_path, |mut output| {
+                io::copy(&mut File::open(&args.image_path)?, &mut output)?;
+                output.flush()?;
+                Ok(())
+            })?;
+            println!(
+                "{} is already {}, copied to {}",
+                args.image_path.display(),
+                interlace,
+                output_file_path.display()
+            );
+            return Ok(());
+        }
+        println!("{} is already {}", args.image_path.display(), interlace);
+        return Ok(());
+    }
+    scanlines.set_interlace(interlace);
+    scanlines.write_to(&mut png)?;
+    write_atomically(&output_file_path, |output| {
+        png.write_to(output)?;
+        Ok(())
+    })?;
+    println!("Wrote {}, {}", output_file_path.display(), interlace);
+    Ok(())
+}
+
+pub fn recompress(args: RecompressArgs) -> Result<()> {
+    let output_file_path = args.output.unwrap_or_else(|| args.image_path.clone());
+    let mut png = Png::from_file(&args.image_path)?;
+    let before = SizeJson::from(&png);
+    idat::recompress(&mut png, args.level, args.chunk_size as usize)?;
+    let after = SizeJson::from(&png);
+    write_atomically(&output_file_path, |output| {
+        png.write_to(output)?;
+        Ok(())
+    })?;
+
+    let delta = after.file_bytes as i64 - before.file_bytes as i64;
+    if args.format == Format::Json {
+        output::print_json(&RecompressJson {
+            output: output_file_path.display().to_string(),
+            before,
+            after,
+            delta,
+        });
+        return Ok(());
+    }
+    println!(
+        "IDAT: {} chunk(s), {} bytes -> {} chunk(s), {} bytes",
+        before.idat_chunks, before.idat_bytes, after.idat_chunks, after.idat_bytes
+    );
+    println!(
+        "Wrote {}: {} -> {} bytes ({:+} bytes, {:+.1}%)",
+        output_file_path.display(),
+        before.file_bytes,
+        after.file_bytes,
+        delta,
+        delta as f64 * 100.0 / before.file_bytes as f64
+    );
+    Ok(())
+}
+
+pub fn export_pixels(args: ExportPixelsArgs) -> Result<()> {
+    let extension = args.output.extension().map(|e| e.to_string_lossy().into_owned());
+    let format = match args.to.or_else(|| extension.as_deref().and_then(PixelFormat::from_extension)) {
+        Some(format) => format,
+        None => {
+            return Err(Error::InvalidPixelFormat { format: extension.unwrap_or_default() });
+        }
+    };
+    let png = Png::from_file(&args.image_path)?;
+    let pixels = pixels::decode(&png)?;
+    write_atomically(&args.output, |output| pixels.write(format, output))?;
+
+    let channels = match pixels.channels {
+        1 => "gray",
+        2 => "gray+alpha",
+        3 => "RGB",
+        _ => "RGBA",
+    };
+    println!(
+        "Wrote {}: {}x{} {}, {}-bit, as {}",
+        args.output.display(),
+        pixels.width,
+        pixels.height,
+        channels,
+        pixels.bit_depth,
+        format
+    );
+    if format == PixelFormat::Pnm && pixels.has_alpha() {
+        println!("Alpha was dropped; use pam to keep it");
+    }
+    Ok(())
+}
+
+/// Exits with a usage error naming the `options` that were given but have no meaning in
+/// lsb mode. Clap can not tie a conflict to the value of --mode.
+fn reject_in_lsb_mode<C: CommandFactory>(options: &[(&str, bool)]) {
+    let given: Vec<&str> = options.iter().filter(|(_, given)| *given).map(|(name, _)| *name).collect();
+    if !given.is_empty() {
+        C::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!("{} can not be used with --mode lsb", given.join(", ")),
+            )
+            .exit();
+    }
+}
+
+/// In lsb mode a password also scatters the hidden bits over the image.
+fn scatter_key(password: Option<&str>) -> Result<Option<ScatterKey>> {
+    password.map(ScatterKey::derive).transpose()
+}
+
+/// Resolves `decode --out`: a directory receives the file under its stored name.
+fn output_path(out: &Path, metadata: Option<&Metadata>) -> Result<PathBuf> {
+    if !out.is_dir() {
+        return Ok(out.to_path_buf());
+    }
+    // Only keep the last component so a stored name can not escape the directory.
+    let file_name = metadata
+        .and_then(|metadata| metadata.file_name.as_deref())
+        .and_then(|name| Path::new(name).file_name());
+    match file_name {
+        Some(file_name) => Ok(out.join(file_name)),
+        None => Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is a directory and the message has no file name", out.display()),
+        ))),
+    }
+}
+
+fn guess_mime_type(file_name: &str) -> String {
+    let extension = Path::new(file_name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+    .to_string()
+}
+
+/// Writes `path` through a temporary file renamed into place once `write` succeeds, so
+/// the input can be streamed into its own path and errors leave no partial output.
+fn write_atomically<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(BufWriter<File>) -> Result<()>,
+{
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".pngme-tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let result = File::create(&temp_path)
+        .map_err(Error::from)
+        .and_then(|file| write(BufWriter::new(file)))
+        .and_then(|()| fs::rename(&temp_path, path).map_err(Error::from));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn file_size(last_chunk: Option<&StreamedChunk>) -> usize {
+    last_chunk.map_or(Png::STANDARD_HEADER.len(), |chunk| {
+        chunk.offset() + 12 + chunk.length() as usize
+    })
+}
+
+fn chunks_json(png: &Png, indices: &[usize]) -> Vec<ChunkJson> {
+    let offsets: Vec<usize> = png.chunks_with_offsets().map(|(offset, _)| offset).collect();
+    indices
+        .iter()
+        .map(|&index| ChunkJson::new(index, offsets[index], &png.chunks()[index]))
+        .collect()
+}
+
+fn yes_no(flag: bool) -> &'static str {
+    if flag {
+        "yes"
+    } else {
+        "no"
+    }
+}
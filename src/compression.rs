@@ -0,0 +1,153 @@
+//! Compression of messages before they are stored.
+//!
+//! Decompression always takes a limit on the output size, so a small chunk can not
+//! expand into an arbitrarily large message.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use crate::{Error, Result};
+
+/// The largest message [`decompress`] produces unless told otherwise: 256 MiB.
+pub const DEFAULT_LIMIT: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Deflate in a zlib stream, as used by PNG itself.
+    Deflate,
+    Zstd,
+    Brotli,
+}
+
+impl Algorithm {
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Algorithm::Deflate => 1,
+            Algorithm::Zstd => 2,
+            Algorithm::Brotli => 3,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Algorithm::Deflate),
+            2 => Some(Algorithm::Zstd),
+            3 => Some(Algorithm::Brotli),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "deflate" => Ok(Algorithm::Deflate),
+            "zstd" => Ok(Algorithm::Zstd),
+            "brotli" => Ok(Algorithm::Brotli),
+            _ => Err(failed(&format!(
+                "unknown algorithm {:?}, expected deflate, zstd or brotli",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::Deflate => write!(f, "deflate"),
+            Algorithm::Zstd => write!(f, "zstd"),
+            Algorithm::Brotli => write!(f, "brotli"),
+        }
+    }
+}
+
+pub fn compress(algorithm: Algorithm, data: &[u8]) -> Result<Vec<u8>> {
+    match algorithm {
+        Algorithm::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(data)?;
+            Ok(encoder.finish()?)
+        }
+        Algorithm::Zstd => Ok(zstd::encode_all(data, 19)?),
+        Algorithm::Brotli => {
+            let mut compressed = Vec::new();
+            let params = brotli::enc::BrotliEncoderParams::default();
+            brotli::BrotliCompress(&mut &data[..], &mut compressed, &params)?;
+            Ok(compressed)
+        }
+    }
+}
+
+/// Decompresses `data`, failing as soon as the output grows past `limit` bytes.
+pub fn decompress(algorithm: Algorithm, data: &[u8], limit: u64) -> Result<Vec<u8>> {
+    let decoder: Box<dyn Read + '_> = match algorithm {
+        Algorithm::Deflate => Box::new(ZlibDecoder::new(data)),
+        Algorithm::Zstd => Box::new(zstd::Decoder::new(data)?),
+        Algorithm::Brotli => Box::new(brotli::Decompressor::new(data, 4096)),
+    };
+    let mut output = Vec::new();
+    decoder
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut output)
+        .map_err(|e: io::Error| failed(&format!("corrupt {} data: {}", algorithm, e)))?;
+    if output.len() as u64 > limit {
+        return Err(failed(&format!("output exceeds the limit of {} bytes", limit)));
+    }
+    Ok(output)
+}
+
+fn failed(reason: &str) -> Error {
+    Error::Decompression { reason: reason.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [Algorithm; 3] = [Algorithm::Deflate, Algorithm::Zstd, Algorithm::Brotli];
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"{\"key\": \"value\", \"key\": \"value\", \"key\": \"value\"}".repeat(20);
+        for algorithm in ALGORITHMS {
+            let compressed = compress(algorithm, &data).unwrap();
+            assert!(compressed.len() < data.len() / 4, "{}", algorithm);
+            assert_eq!(decompress(algorithm, &compressed, DEFAULT_LIMIT).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_limit_stops_bombs() {
+        let data = vec![0; 1 << 20];
+        for algorithm in ALGORITHMS {
+            let compressed = compress(algorithm, &data).unwrap();
+            assert!(decompress(algorithm, &compressed, 1 << 20).is_ok());
+            assert!(matches!(
+                decompress(algorithm, &compressed, 1000),
+                Err(Error::Decompression { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_corrupt_data() {
+        for algorithm in ALGORITHMS {
+            assert!(decompress(algorithm, b"not compressed at all", DEFAULT_LIMIT).is_err());
+        }
+    }
+
+    #[test]
+    fn test_algorithm_ids() {
+        for algorithm in ALGORITHMS {
+            assert_eq!(Algorithm::from_id(algorithm.id()), Some(algorithm));
+            assert_eq!(algorithm.to_string().parse::<Algorithm>().unwrap(), algorithm);
+        }
+    }
+}
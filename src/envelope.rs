@@ -0,0 +1,1026 @@
+//! The versioned container stored in chunk data when a message is protected.
+//!
+//! An envelope starts with the 4 byte [`MAGIC`] and a version byte, followed by fields
+//! encoded as a tag byte, a big endian `u32` length and the value. Messages stored
+//! without any protection are kept as raw bytes and never wrapped in an envelope.
+//!
+//! Version 1 defines these fields:
+//!
+//! | Tag | Field   | Value                                                             |
+//! |-----|---------|-------------------------------------------------------------------|
+//! | 1   | payload | the stored bytes, ciphertext if encrypted                         |
+//! | 2   | kdf     | `1` (Argon2id), `m_cost`, `t_cost`, `p_cost` as `u32`, then salt  |
+//! | 3   | cipher  | `1` (ChaCha20-Poly1305), then the 12 byte nonce                   |
+//! | 4   | agreement | `1` (X25519), then the sender's 32 byte ephemeral public key    |
+//! | 5   | signature | `1` (Ed25519), a flag byte, then the 64 byte signature          |
+//! | 6   | metadata  | size as `u64`, then file name and MIME type, each as a `u16` length and UTF-8 |
+//! | 7   | part      | message id `u64`, sequence number, total and CRC-32 as `u32`    |
+//! | 8   | compression | algorithm id (see [`Algorithm`]), then the original size as `u64` |
+//!
+//! Password encrypted messages carry `kdf` and `cipher`. Messages encrypted for a
+//! [`Recipient`] carry `agreement` and `cipher`; their key is HKDF-SHA256 of the X25519
+//! shared secret, salted with the ephemeral and recipient public keys.
+//!
+//! A signature is always the last field. It covers the chunk type and the envelope
+//! written without it, so it stays valid whatever else the envelope holds. When the
+//! flag byte is `1` it also covers the SHA-256 digest of the image's IDAT data, tying
+//! the message to one image.
+//!
+//! [`Metadata`] describes a stored file, and `compression` records how the payload was
+//! compressed. An encrypted message keeps both private by encrypting an inner envelope
+//! holding them and the payload, so the outer envelope never carries these fields.
+//!
+//! Stored data split across several chunks by [`crate::parts`] is carried in envelopes
+//! holding only a `part` and a `payload` with one slice of the data.
+
+use argon2::{Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::fmt;
+
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret};
+
+use crate::chunk_type::ChunkType;
+use crate::compression::{self, Algorithm};
+use crate::keys::{Identity, Recipient, SigningKey, VerifyingKey};
+use crate::{Error, Result};
+
+/// Marks chunk data as an envelope. Like the PNG signature it starts with a byte
+/// that can not begin valid UTF-8, so plain text messages are never mistaken for one.
+pub const MAGIC: [u8; 4] = [0x89, b'P', b'M', b'E'];
+pub const VERSION: u8 = 1;
+
+const TAG_PAYLOAD: u8 = 1;
+const TAG_KDF: u8 = 2;
+const TAG_CIPHER: u8 = 3;
+const TAG_AGREEMENT: u8 = 4;
+const TAG_SIGNATURE: u8 = 5;
+const TAG_METADATA: u8 = 6;
+const TAG_PART: u8 = 7;
+const TAG_COMPRESSION: u8 = 8;
+
+const KDF_ARGON2ID: u8 = 1;
+const CIPHER_CHACHA20_POLY1305: u8 = 1;
+const AGREEMENT_X25519: u8 = 1;
+const SIGNATURE_ED25519: u8 = 1;
+
+const X25519_INFO: &[u8] = b"pngme x25519 v1";
+const SIGNATURE_CONTEXT: &[u8] = b"pngme signature v1";
+
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const KEY_LENGTH: usize = 32;
+
+/// Cost parameters for Argon2id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// Memory size in KiB.
+    pub m_cost: u32,
+    /// Number of passes.
+    pub t_cost: u32,
+    /// Degree of parallelism.
+    pub p_cost: u32,
+}
+
+impl Default for Argon2Params {
+    /// The OWASP recommended minimum: 19 MiB of memory, 2 passes, 1 lane.
+    fn default() -> Self {
+        Argon2Params {
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+impl Argon2Params {
+    /// The most a stored envelope may ask for, so opening an untrusted image
+    /// can not exhaust memory or stall for minutes.
+    pub const MAX: Argon2Params = Argon2Params {
+        m_cost: 256 * 1024,
+        t_cost: 16,
+        p_cost: 8,
+    };
+
+    fn within_limits(&self) -> bool {
+        self.m_cost <= Self::MAX.m_cost
+            && self.t_cost <= Self::MAX.t_cost
+            && self.p_cost <= Self::MAX.p_cost
+    }
+}
+
+/// How the encryption key is derived from a password.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kdf {
+    Argon2id { params: Argon2Params, salt: Vec<u8> },
+}
+
+/// How the payload is encrypted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cipher {
+    ChaCha20Poly1305 { nonce: [u8; NONCE_LENGTH] },
+}
+
+/// How the encryption key is agreed with a recipient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyAgreement {
+    X25519 { ephemeral: [u8; 32] },
+}
+
+/// Describes a file stored as the message, so it can be restored as it was.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Metadata {
+    pub file_name: Option<String>,
+    pub mime_type: Option<String>,
+    /// Size of the file in bytes.
+    pub size: u64,
+}
+
+/// How the payload was compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub algorithm: Algorithm,
+    /// Size of the payload once decompressed.
+    pub size: u64,
+}
+
+/// Locates a slice of stored data split across several chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Part {
+    /// Shared by every part of the same data.
+    pub message_id: u64,
+    /// Position of this part, counting from 0.
+    pub sequence: u32,
+    pub total: u32,
+    /// CRC-32 of the whole data.
+    pub checksum: u32,
+}
+
+/// Who vouches for the message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signature {
+    Ed25519 { covers_idat: bool, signature: [u8; 64] },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Envelope {
+    pub kdf: Option<Kdf>,
+    pub key_agreement: Option<KeyAgreement>,
+    pub cipher: Option<Cipher>,
+    pub metadata: Option<Metadata>,
+    pub compression: Option<Compression>,
+    pub part: Option<Part>,
+    pub payload: Vec<u8>,
+    pub signature: Option<Signature>,
+}
+
+impl Envelope {
+    /// Whether `data` starts like an envelope.
+    pub fn is_envelope(data: &[u8]) -> bool {
+        data.starts_with(&MAGIC)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        if let Some(Kdf::Argon2id { params, salt }) = &self.kdf {
+            let mut value = vec![KDF_ARGON2ID];
+            for cost in [params.m_cost, params.t_cost, params.p_cost] {
+                value.extend_from_slice(&cost.to_be_bytes());
+            }
+            value.extend_from_slice(salt);
+            push_field(&mut bytes, TAG_KDF, &value);
+        }
+        if let Some(KeyAgreement::X25519 { ephemeral }) = &self.key_agreement {
+            let value: Vec<u8> = [AGREEMENT_X25519].iter().chain(ephemeral).copied().collect();
+            push_field(&mut bytes, TAG_AGREEMENT, &value);
+        }
+        if let Some(Cipher::ChaCha20Poly1305 { nonce }) = &self.cipher {
+            let value: Vec<u8> = [CIPHER_CHACHA20_POLY1305].iter().chain(nonce).copied().collect();
+            push_field(&mut bytes, TAG_CIPHER, &value);
+        }
+        if let Some(metadata) = &self.metadata {
+            let mut value = metadata.size.to_be_bytes().to_vec();
+            for text in [&metadata.file_name, &metadata.mime_type] {
+                let text = text.as_deref().unwrap_or_default();
+                value.extend_from_slice(&(text.len() as u16).to_be_bytes());
+                value.extend_from_slice(text.as_bytes());
+            }
+            push_field(&mut bytes, TAG_METADATA, &value);
+        }
+        if let Some(Compression { algorithm, size }) = &self.compression {
+            let mut value = vec![algorithm.id()];
+            value.extend_from_slice(&size.to_be_bytes());
+            push_field(&mut bytes, TAG_COMPRESSION, &value);
+        }
+        if let Some(part) = &self.part {
+            let mut value = part.message_id.to_be_bytes().to_vec();
+            for field in [part.sequence, part.total, part.checksum] {
+                value.extend_from_slice(&field.to_be_bytes());
+            }
+            push_field(&mut bytes, TAG_PART, &value);
+        }
+        push_field(&mut bytes, TAG_PAYLOAD, &self.payload);
+        if let Some(Signature::Ed25519 { covers_idat, signature }) = &self.signature {
+            let value: Vec<u8> = [SIGNATURE_ED25519, *covers_idat as u8]
+                .iter()
+                .chain(signature)
+                .copied()
+                .collect();
+            push_field(&mut bytes, TAG_SIGNATURE, &value);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let rest = bytes
+            .strip_prefix(&MAGIC)
+            .ok_or_else(|| invalid("missing magic bytes"))?;
+        let (&version, mut rest) = rest.split_first().ok_or_else(|| invalid("missing version"))?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+
+        let mut envelope = Envelope::default();
+        let mut payload = None;
+        while !rest.is_empty() {
+            let (tag, value, remaining) = read_field(rest)?;
+            rest = remaining;
+            match tag {
+                TAG_PAYLOAD => payload = Some(value.to_vec()),
+                TAG_KDF => envelope.kdf = Some(parse_kdf(value)?),
+                TAG_CIPHER => envelope.cipher = Some(parse_cipher(value)?),
+                TAG_AGREEMENT => envelope.key_agreement = Some(parse_agreement(value)?),
+                TAG_METADATA => envelope.metadata = Some(parse_metadata(value)?),
+                TAG_PART => envelope.part = Some(parse_part(value)?),
+                TAG_COMPRESSION => envelope.compression = Some(parse_compression(value)?),
+                TAG_SIGNATURE if rest.is_empty() => {
+                    envelope.signature = Some(parse_signature(value)?)
+                }
+                TAG_SIGNATURE => return Err(invalid("the signature must be the last field")),
+                _ => return Err(invalid(&format!("unknown field {}", tag))),
+            }
+        }
+        envelope.payload = payload.ok_or_else(|| invalid("missing payload"))?;
+        Ok(envelope)
+    }
+}
+
+/// What [`seal`] should do to a message. The default stores it as is.
+#[derive(Debug, Clone, Default)]
+pub struct SealOptions<'a> {
+    /// Encrypt with a key derived from this password.
+    pub password: Option<&'a str>,
+    pub argon2: Argon2Params,
+    /// Encrypt so that only the matching [`Identity`] can read the message.
+    pub recipient: Option<&'a Recipient>,
+    /// Describe the message as a file.
+    pub metadata: Option<&'a Metadata>,
+    /// Compress the message before encrypting it.
+    pub compression: Option<Algorithm>,
+}
+
+/// Keys [`open`] may use to recover a message.
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions<'a> {
+    pub password: Option<&'a str>,
+    pub identity: Option<&'a Identity>,
+    /// Refuse to decompress messages larger than this many bytes.
+    /// Defaults to [`compression::DEFAULT_LIMIT`].
+    pub decompression_limit: Option<u64>,
+}
+
+/// A message recovered by [`open`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub data: Vec<u8>,
+    pub metadata: Option<Metadata>,
+}
+
+/// Turns `message` into the data to store in a chunk.
+///
+/// Without any option set the message is returned unchanged.
+pub fn seal(message: &[u8], options: &SealOptions) -> Result<Vec<u8>> {
+    if let Some(metadata) = options.metadata {
+        // Each text is stored after a 16-bit length.
+        for text in [&metadata.file_name, &metadata.mime_type].into_iter().flatten() {
+            if text.len() > u16::MAX as usize {
+                return Err(invalid("file name or MIME type is longer than 65535 bytes"));
+            }
+        }
+    }
+    let inner = Envelope {
+        metadata: options.metadata.cloned(),
+        compression: options.compression.map(|algorithm| Compression {
+            algorithm,
+            size: message.len() as u64,
+        }),
+        payload: match options.compression {
+            Some(algorithm) => compression::compress(algorithm, message)?,
+            None => message.to_vec(),
+        },
+        ..Default::default()
+    };
+    let message = match inner.metadata.is_some() || inner.compression.is_some() {
+        true => inner.to_bytes(),
+        false => inner.payload,
+    };
+
+    let mut envelope = Envelope::default();
+    let key = match (options.password, options.recipient) {
+        (None, None) => return Ok(message),
+        (Some(_), Some(_)) => {
+            return Err(invalid("a message is encrypted with a password or for a recipient, not both"))
+        }
+        (Some(password), None) => {
+            if !options.argon2.within_limits() {
+                return Err(invalid("Argon2id parameters are above the allowed maximum"));
+            }
+            let mut salt = vec![0; SALT_LENGTH];
+            OsRng.fill_bytes(&mut salt);
+            let kdf = Kdf::Argon2id { params: options.argon2, salt };
+            let key = derive_key(&kdf, password)?;
+            envelope.kdf = Some(kdf);
+            key
+        }
+        (None, Some(recipient)) => {
+            let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+            let ephemeral_public = PublicKey::from(&ephemeral);
+            let shared = ephemeral.diffie_hellman(recipient.public_key());
+            let key = agreed_key(&shared, &ephemeral_public, recipient.public_key())?;
+            envelope.key_agreement = Some(KeyAgreement::X25519 {
+                ephemeral: ephemeral_public.to_bytes(),
+            });
+            key
+        }
+    };
+
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    envelope.payload = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt(&nonce, message.as_slice())
+        .map_err(|_| invalid("encryption failed"))?;
+    envelope.cipher = Some(Cipher::ChaCha20Poly1305 { nonce: nonce.into() });
+    Ok(envelope.to_bytes())
+}
+
+/// Recovers the message stored in chunk `data` by [`seal`].
+///
+/// Data that is not an envelope is returned unchanged.
+pub fn open(data: &[u8], options: &OpenOptions) -> Result<Message> {
+    if !Envelope::is_envelope(data) {
+        return Ok(Message { data: data.to_vec(), metadata: None });
+    }
+    let envelope = Envelope::from_bytes(data)?;
+    if envelope.part.is_some() {
+        return Err(invalid("this is one part of a split message, reassemble it first"));
+    }
+    let key = match (&envelope.kdf, &envelope.key_agreement) {
+        (None, None) if envelope.cipher.is_none() => return into_message(envelope, options),
+        (Some(kdf), None) => {
+            let password = options
+                .password
+                .ok_or(Error::KeyRequired { scheme: "a password" })?;
+            derive_key(kdf, password)?
+        }
+        (None, Some(KeyAgreement::X25519 { ephemeral })) => {
+            let identity = options
+                .identity
+                .ok_or(Error::KeyRequired { scheme: "a public key" })?;
+            let ephemeral = PublicKey::from(*ephemeral);
+            let shared = identity.secret().diffie_hellman(&ephemeral);
+            agreed_key(&shared, &ephemeral, identity.recipient().public_key())?
+        }
+        _ => return Err(invalid("a cipher needs exactly one of kdf and agreement")),
+    };
+    let Some(Cipher::ChaCha20Poly1305 { nonce }) = &envelope.cipher else {
+        return Err(invalid("missing cipher"));
+    };
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .decrypt(Nonce::from_slice(nonce), envelope.payload.as_slice())
+        .map_err(|_| Error::DecryptionFailed)?;
+    if !Envelope::is_envelope(&plaintext) {
+        return Ok(Message { data: plaintext, metadata: None });
+    }
+    let inner = Envelope::from_bytes(&plaintext)?;
+    if inner.cipher.is_some() || inner.signature.is_some() || inner.part.is_some() {
+        return Err(invalid(
+            "an encrypted envelope may only hold metadata, compression and payload",
+        ));
+    }
+    into_message(inner, options)
+}
+
+/// Decompresses the payload of an envelope that needs no key.
+fn into_message(envelope: Envelope, options: &OpenOptions) -> Result<Message> {
+    let data = match envelope.compression {
+        Some(Compression { algorithm, size }) => {
+            let limit = options.decompression_limit.unwrap_or(compression::DEFAULT_LIMIT);
+            if size > limit {
+                return Err(Error::Decompression {
+                    reason: format!("message would be {} bytes, over the limit of {}", size, limit),
+                });
+            }
+            let data = compression::decompress(algorithm, &envelope.payload, size)?;
+            if data.len() as u64 != size {
+                return Err(Error::Decompression {
+                    reason: format!("expected {} bytes, got {}", size, data.len()),
+                });
+            }
+            data
+        }
+        None => envelope.payload,
+    };
+    Ok(Message { data, metadata: envelope.metadata })
+}
+
+/// The outcome of checking the signature of a chunk with [`verify`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    Valid,
+    Invalid,
+    Unsigned,
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verification::Valid => write!(f, "valid"),
+            Verification::Invalid => write!(f, "invalid"),
+            Verification::Unsigned => write!(f, "unsigned"),
+        }
+    }
+}
+
+/// Signs chunk `data` made by [`seal`] for a chunk of type `chunk_type`, wrapping plain
+/// messages in an envelope. Passing the image's `idat_digest` ties the signature to the
+/// pixel data as well.
+pub fn sign(
+    data: &[u8],
+    chunk_type: &ChunkType,
+    key: &SigningKey,
+    idat_digest: Option<&[u8; 32]>,
+) -> Result<Vec<u8>> {
+    let mut envelope = if Envelope::is_envelope(data) {
+        Envelope::from_bytes(data)?
+    } else {
+        Envelope { payload: data.to_vec(), ..Default::default() }
+    };
+    envelope.signature = None;
+    let message = signed_message(chunk_type, &envelope.to_bytes(), idat_digest);
+    envelope.signature = Some(Signature::Ed25519 {
+        covers_idat: idat_digest.is_some(),
+        signature: key.sign(&message),
+    });
+    Ok(envelope.to_bytes())
+}
+
+/// Checks the signature on the data of a chunk of type `chunk_type` against a trusted
+/// `key`. `idat_digest` is only used when the signature covers the image data.
+pub fn verify(
+    data: &[u8],
+    chunk_type: &ChunkType,
+    key: &VerifyingKey,
+    idat_digest: &[u8; 32],
+) -> Verification {
+    if !Envelope::is_envelope(data) {
+        return Verification::Unsigned;
+    }
+    let Ok(mut envelope) = Envelope::from_bytes(data) else {
+        return Verification::Invalid;
+    };
+    let Some(Signature::Ed25519 { covers_idat, signature }) = envelope.signature.take() else {
+        return Verification::Unsigned;
+    };
+    let message = signed_message(
+        chunk_type,
+        &envelope.to_bytes(),
+        covers_idat.then_some(idat_digest),
+    );
+    if key.verify(&message, &signature) {
+        Verification::Valid
+    } else {
+        Verification::Invalid
+    }
+}
+
+fn signed_message(chunk_type: &ChunkType, unsigned: &[u8], idat_digest: Option<&[u8; 32]>) -> Vec<u8> {
+    let mut message = SIGNATURE_CONTEXT.to_vec();
+    message.extend_from_slice(&chunk_type.bytes());
+    match idat_digest {
+        Some(digest) => {
+            message.push(1);
+            message.extend_from_slice(digest);
+        }
+        None => message.push(0),
+    }
+    message.extend_from_slice(unsigned);
+    message
+}
+
+fn agreed_key(
+    shared: &SharedSecret,
+    ephemeral: &PublicKey,
+    recipient: &PublicKey,
+) -> Result<[u8; KEY_LENGTH]> {
+    // A low order public key forces a known shared secret.
+    if !shared.was_contributory() {
+        return Err(Error::InvalidKey {
+            reason: "X25519 key agreement produced a non-contributory secret".to_string(),
+        });
+    }
+    let salt: Vec<u8> = ephemeral.as_bytes().iter().chain(recipient.as_bytes()).copied().collect();
+    let mut key = [0; KEY_LENGTH];
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(X25519_INFO, &mut key)
+        .map_err(|_| invalid("key expansion failed"))?;
+    Ok(key)
+}
+
+pub(crate) fn derive_key(kdf: &Kdf, password: &str) -> Result<[u8; KEY_LENGTH]> {
+    let Kdf::Argon2id { params, salt } = kdf;
+    let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(KEY_LENGTH))
+        .map_err(|e| invalid(&format!("bad Argon2 parameters: {}", e)))?;
+    let mut key = [0; KEY_LENGTH];
+    Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| invalid(&format!("key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+fn push_field(bytes: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    bytes.push(tag);
+    bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(value);
+}
+
+fn read_field(bytes: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    if bytes.len() < 5 {
+        return Err(invalid("truncated field header"));
+    }
+    let length = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]) as usize;
+    let rest = &bytes[5..];
+    if rest.len() < length {
+        return Err(invalid("truncated field value"));
+    }
+    Ok((bytes[0], &rest[..length], &rest[length..]))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn parse_kdf(value: &[u8]) -> Result<Kdf> {
+    match value.first() {
+        Some(&KDF_ARGON2ID) if value.len() > 13 => {
+            let params = Argon2Params {
+                m_cost: read_u32(value, 1),
+                t_cost: read_u32(value, 5),
+                p_cost: read_u32(value, 9),
+            };
+            if !params.within_limits() {
+                return Err(invalid("Argon2id parameters are above the allowed maximum"));
+            }
+            Ok(Kdf::Argon2id { params, salt: value[13..].to_vec() })
+        }
+        Some(&KDF_ARGON2ID) => Err(invalid("truncated Argon2id parameters")),
+        Some(other) => Err(invalid(&format!("unknown kdf {}", other))),
+        None => Err(invalid("empty kdf field")),
+    }
+}
+
+fn parse_cipher(value: &[u8]) -> Result<Cipher> {
+    match value.first() {
+        Some(&CIPHER_CHACHA20_POLY1305) if value.len() == 1 + NONCE_LENGTH => {
+            let mut nonce = [0; NONCE_LENGTH];
+            nonce.copy_from_slice(&value[1..]);
+            Ok(Cipher::ChaCha20Poly1305 { nonce })
+        }
+        Some(&CIPHER_CHACHA20_POLY1305) => Err(invalid("bad ChaCha20-Poly1305 nonce length")),
+        Some(other) => Err(invalid(&format!("unknown cipher {}", other))),
+        None => Err(invalid("empty cipher field")),
+    }
+}
+
+fn parse_agreement(value: &[u8]) -> Result<KeyAgreement> {
+    match value.first() {
+        Some(&AGREEMENT_X25519) if value.len() == 33 => {
+            let mut ephemeral = [0; 32];
+            ephemeral.copy_from_slice(&value[1..]);
+            Ok(KeyAgreement::X25519 { ephemeral })
+        }
+        Some(&AGREEMENT_X25519) => Err(invalid("bad X25519 public key length")),
+        Some(other) => Err(invalid(&format!("unknown key agreement {}", other))),
+        None => Err(invalid("empty agreement field")),
+    }
+}
+
+fn parse_metadata(value: &[u8]) -> Result<Metadata> {
+    let size = value
+        .get(..8)
+        .ok_or_else(|| invalid("truncated metadata"))?;
+    let mut rest = &value[8..];
+    let mut texts = [None, None];
+    for text in &mut texts {
+        let length = match rest {
+            [high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+            _ => return Err(invalid("truncated metadata")),
+        };
+        let bytes = rest
+            .get(2..2 + length)
+            .ok_or_else(|| invalid("truncated metadata"))?;
+        let string = String::from_utf8(bytes.to_vec())
+            .map_err(|_| invalid("metadata is not valid UTF-8"))?;
+        *text = Some(string).filter(|s| !s.is_empty());
+        rest = &rest[2 + length..];
+    }
+    if !rest.is_empty() {
+        return Err(invalid("trailing bytes in metadata"));
+    }
+    let [file_name, mime_type] = texts;
+    Ok(Metadata {
+        file_name,
+        mime_type,
+        size: u64::from_be_bytes(size.try_into().unwrap()),
+    })
+}
+
+fn parse_compression(value: &[u8]) -> Result<Compression> {
+    match value {
+        [id, size @ ..] if size.len() == 8 => Ok(Compression {
+            algorithm: Algorithm::from_id(*id)
+                .ok_or_else(|| invalid(&format!("unknown compression {}", id)))?,
+            size: u64::from_be_bytes(size.try_into().unwrap()),
+        }),
+        _ => Err(invalid("bad compression field length")),
+    }
+}
+
+fn parse_part(value: &[u8]) -> Result<Part> {
+    if value.len() != 20 {
+        return Err(invalid("bad part field length"));
+    }
+    Ok(Part {
+        message_id: u64::from_be_bytes(value[..8].try_into().unwrap()),
+        sequence: read_u32(value, 8),
+        total: read_u32(value, 12),
+        checksum: read_u32(value, 16),
+    })
+}
+
+fn parse_signature(value: &[u8]) -> Result<Signature> {
+    match value {
+        [SIGNATURE_ED25519, flag @ (0 | 1), signature @ ..] if signature.len() == 64 => {
+            Ok(Signature::Ed25519 {
+                covers_idat: *flag == 1,
+                signature: signature.try_into().unwrap(),
+            })
+        }
+        [SIGNATURE_ED25519, ..] => Err(invalid("bad Ed25519 signature field")),
+        [other, ..] => Err(invalid(&format!("unknown signature scheme {}", other))),
+        [] => Err(invalid("empty signature field")),
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidEnvelope { reason: reason.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keeps the tests fast; real messages use the default cost.
+    const CHEAP: Argon2Params = Argon2Params {
+        m_cost: 8,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn sealed(message: &[u8], password: &str) -> Vec<u8> {
+        let options = SealOptions {
+            password: Some(password),
+            argon2: CHEAP,
+            ..Default::default()
+        };
+        seal(message, &options).unwrap()
+    }
+
+    #[test]
+    fn test_seal_without_options_is_identity() {
+        let data = seal(b"hello", &SealOptions::default()).unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(open(&data, &OpenOptions::default()).unwrap().data, b"hello");
+    }
+
+    #[test]
+    fn test_password_round_trip() {
+        let data = sealed(b"hello", "correct horse");
+        assert!(Envelope::is_envelope(&data));
+        assert!(!data.windows(5).any(|w| w == b"hello"));
+
+        let options = OpenOptions {
+            password: Some("correct horse"),
+            ..Default::default()
+        };
+        assert_eq!(open(&data, &options).unwrap().data, b"hello");
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        let data = sealed(b"hello", "correct horse");
+        let options = OpenOptions {
+            password: Some("battery staple"),
+            ..Default::default()
+        };
+        assert!(matches!(open(&data, &options), Err(Error::DecryptionFailed)));
+    }
+
+    #[test]
+    fn test_missing_password() {
+        let data = sealed(b"hello", "correct horse");
+        assert!(matches!(
+            open(&data, &OpenOptions::default()),
+            Err(Error::KeyRequired { .. })
+        ));
+    }
+
+    #[test]
+    fn test_recipient_round_trip() {
+        let identity = Identity::generate();
+        let recipient = identity.recipient();
+        let options = SealOptions {
+            recipient: Some(&recipient),
+            ..Default::default()
+        };
+        let data = seal(b"hello", &options).unwrap();
+        assert!(matches!(
+            Envelope::from_bytes(&data).unwrap().key_agreement,
+            Some(KeyAgreement::X25519 { .. })
+        ));
+
+        let options = OpenOptions {
+            identity: Some(&identity),
+            ..Default::default()
+        };
+        assert_eq!(open(&data, &options).unwrap().data, b"hello");
+    }
+
+    #[test]
+    fn test_wrong_identity() {
+        let recipient = Identity::generate().recipient();
+        let options = SealOptions {
+            recipient: Some(&recipient),
+            ..Default::default()
+        };
+        let data = seal(b"hello", &options).unwrap();
+
+        assert!(matches!(
+            open(&data, &OpenOptions::default()),
+            Err(Error::KeyRequired { .. })
+        ));
+        let other = Identity::generate();
+        let options = OpenOptions {
+            identity: Some(&other),
+            ..Default::default()
+        };
+        assert!(matches!(open(&data, &options), Err(Error::DecryptionFailed)));
+    }
+
+    #[test]
+    fn test_password_and_recipient_are_exclusive() {
+        let recipient = Identity::generate().recipient();
+        let options = SealOptions {
+            password: Some("correct horse"),
+            recipient: Some(&recipient),
+            ..Default::default()
+        };
+        assert!(seal(b"hello", &options).is_err());
+    }
+
+    fn chunk_type() -> ChunkType {
+        "RuSt".parse().unwrap()
+    }
+
+    #[test]
+    fn test_sign_then_verify() {
+        let key = SigningKey::generate();
+        let trusted = key.verifying_key();
+        let digest = [9; 32];
+
+        let data = sign(b"hello", &chunk_type(), &key, None).unwrap();
+        assert_eq!(open(&data, &OpenOptions::default()).unwrap().data, b"hello");
+        assert_eq!(verify(&data, &chunk_type(), &trusted, &digest), Verification::Valid);
+
+        let other = SigningKey::generate().verifying_key();
+        assert_eq!(verify(&data, &chunk_type(), &other, &digest), Verification::Invalid);
+        let moved: ChunkType = "MoVe".parse().unwrap();
+        assert_eq!(verify(&data, &moved, &trusted, &digest), Verification::Invalid);
+        assert_eq!(verify(b"hello", &chunk_type(), &trusted, &digest), Verification::Unsigned);
+    }
+
+    #[test]
+    fn test_signature_covers_payload_and_idat() {
+        let key = SigningKey::generate();
+        let trusted = key.verifying_key();
+        let data = sign(b"hello", &chunk_type(), &key, Some(&[9; 32])).unwrap();
+        assert_eq!(verify(&data, &chunk_type(), &trusted, &[9; 32]), Verification::Valid);
+        assert_eq!(verify(&data, &chunk_type(), &trusted, &[8; 32]), Verification::Invalid);
+
+        let mut tampered = data.clone();
+        let at = tampered.windows(5).position(|w| w == b"hello").unwrap();
+        tampered[at] = b'j';
+        assert_eq!(verify(&tampered, &chunk_type(), &trusted, &[9; 32]), Verification::Invalid);
+    }
+
+    #[test]
+    fn test_sign_encrypted_message() {
+        let key = SigningKey::generate();
+        let data = sign(&sealed(b"hello", "correct horse"), &chunk_type(), &key, None).unwrap();
+        let trusted = key.verifying_key();
+        assert_eq!(verify(&data, &chunk_type(), &trusted, &[0; 32]), Verification::Valid);
+        let options = OpenOptions {
+            password: Some("correct horse"),
+            ..Default::default()
+        };
+        assert_eq!(open(&data, &options).unwrap().data, b"hello");
+    }
+
+    fn metadata() -> Metadata {
+        Metadata {
+            file_name: Some("hello.txt".to_string()),
+            mime_type: Some("text/plain".to_string()),
+            size: 5,
+        }
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let metadata = metadata();
+        let options = SealOptions {
+            metadata: Some(&metadata),
+            ..Default::default()
+        };
+        let data = seal(b"hello", &options).unwrap();
+        let message = open(&data, &OpenOptions::default()).unwrap();
+        assert_eq!(message.data, b"hello");
+        assert_eq!(message.metadata, Some(metadata));
+    }
+
+    #[test]
+    fn test_rejects_oversized_metadata() {
+        let oversized = Metadata {
+            mime_type: Some("x".repeat(u16::MAX as usize + 1)),
+            ..metadata()
+        };
+        let options = SealOptions {
+            metadata: Some(&oversized),
+            ..Default::default()
+        };
+        assert!(matches!(seal(b"hello", &options), Err(Error::InvalidEnvelope { .. })));
+
+        let metadata = Metadata {
+            file_name: Some("x".repeat(u16::MAX as usize)),
+            ..metadata()
+        };
+        let options = SealOptions {
+            metadata: Some(&metadata),
+            ..Default::default()
+        };
+        let message = open(&seal(b"hello", &options).unwrap(), &OpenOptions::default()).unwrap();
+        assert_eq!(message.metadata, Some(metadata));
+    }
+
+    #[test]
+    fn test_encrypted_metadata_is_private() {
+        let metadata = metadata();
+        let options = SealOptions {
+            password: Some("correct horse"),
+            argon2: CHEAP,
+            metadata: Some(&metadata),
+            ..Default::default()
+        };
+        let data = seal(b"hello", &options).unwrap();
+        assert!(Envelope::from_bytes(&data).unwrap().metadata.is_none());
+        assert!(!data.windows(9).any(|w| w == b"hello.txt"));
+
+        let options = OpenOptions {
+            password: Some("correct horse"),
+            ..Default::default()
+        };
+        let message = open(&data, &options).unwrap();
+        assert_eq!(message.data, b"hello");
+        assert_eq!(message.metadata, Some(metadata));
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let message = b"compress me, compress me, compress me, compress me".repeat(10);
+        let options = SealOptions {
+            compression: Some(Algorithm::Brotli),
+            ..Default::default()
+        };
+        let data = seal(&message, &options).unwrap();
+        assert!(data.len() < message.len() / 4);
+        assert_eq!(open(&data, &OpenOptions::default()).unwrap().data, message);
+
+        let options = SealOptions {
+            password: Some("correct horse"),
+            argon2: CHEAP,
+            compression: Some(Algorithm::Deflate),
+            ..Default::default()
+        };
+        let data = seal(&message, &options).unwrap();
+        assert!(Envelope::from_bytes(&data).unwrap().compression.is_none());
+        let options = OpenOptions {
+            password: Some("correct horse"),
+            ..Default::default()
+        };
+        assert_eq!(open(&data, &options).unwrap().data, message);
+    }
+
+    #[test]
+    fn test_decompression_limit() {
+        let options = SealOptions {
+            compression: Some(Algorithm::Zstd),
+            ..Default::default()
+        };
+        let data = seal(&[0; 10_000], &options).unwrap();
+        let options = OpenOptions {
+            decompression_limit: Some(1000),
+            ..Default::default()
+        };
+        assert!(matches!(open(&data, &options), Err(Error::Decompression { .. })));
+
+        // A lying size field does not get past the limit either.
+        let mut envelope = Envelope::from_bytes(&data).unwrap();
+        envelope.compression = Some(Compression { algorithm: Algorithm::Zstd, size: 10 });
+        let result = open(&envelope.to_bytes(), &OpenOptions::default());
+        assert!(matches!(result, Err(Error::Decompression { .. })));
+    }
+
+    #[test]
+    fn test_open_rejects_oversized_argon2_params() {
+        let mut envelope = Envelope::from_bytes(&sealed(b"secret", "hunter2")).unwrap();
+        envelope.kdf = Some(Kdf::Argon2id {
+            params: Argon2Params { m_cost: 4 * 1024 * 1024, ..CHEAP },
+            salt: vec![7; SALT_LENGTH],
+        });
+        let options = OpenOptions {
+            password: Some("hunter2"),
+            ..Default::default()
+        };
+        let result = open(&envelope.to_bytes(), &options);
+        assert!(matches!(result, Err(Error::InvalidEnvelope { .. })));
+    }
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let envelope = Envelope {
+            kdf: Some(Kdf::Argon2id {
+                params: CHEAP,
+                salt: vec![7; SALT_LENGTH],
+            }),
+            key_agreement: None,
+            cipher: Some(Cipher::ChaCha20Poly1305 { nonce: [3; NONCE_LENGTH] }),
+            metadata: Some(Metadata {
+                file_name: Some("notes.txt".to_string()),
+                mime_type: None,
+                size: 10,
+            }),
+            compression: Some(Compression {
+                algorithm: Algorithm::Zstd,
+                size: 1000,
+            }),
+            part: Some(Part {
+                message_id: 42,
+                sequence: 1,
+                total: 3,
+                checksum: 0xdead_beef,
+            }),
+            payload: b"ciphertext".to_vec(),
+            signature: Some(Signature::Ed25519 { covers_idat: true, signature: [5; 64] }),
+        };
+        let bytes = envelope.to_bytes();
+        assert_eq!(bytes[..5], [0x89, b'P', b'M', b'E', VERSION]);
+        assert_eq!(Envelope::from_bytes(&bytes).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_rejects_unknown_version() {
+        let mut bytes = Envelope::default().to_bytes();
+        bytes[4] = 9;
+        assert!(matches!(
+            Envelope::from_bytes(&bytes),
+            Err(Error::InvalidEnvelope { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_truncated_envelope() {
+        let bytes = sealed(b"hello", "correct horse");
+        assert!(Envelope::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
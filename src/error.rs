@@ -0,0 +1,151 @@
+use std::fmt;
+use std::io;
+use std::string::FromUtf8Error;
+
+/// Everything that can go wrong while reading, editing or writing a PNG.
+#[derive(Debug)]
+pub enum Error {
+    /// The first 8 bytes are not the PNG signature.
+    BadSignature,
+    /// A chunk declares a data length above the 2^31 - 1 limit of the spec.
+    ChunkTooLong { offset: usize, length: u32 },
+    /// The input ended in the middle of the chunk starting at `offset`.
+    TruncatedChunk { offset: usize },
+    /// The CRC stored in the chunk at `offset` does not match its contents.
+    CrcMismatch { offset: usize, expected: u32, actual: u32 },
+    /// A chunk type is not made of 4 valid bytes.
+    InvalidChunkType { chunk_type: String },
+    /// No chunk of the requested type exists.
+    ChunkNotFound { chunk_type: String },
+    /// The `IHDR` chunk is malformed or describes an illegal image.
+    InvalidHeader { reason: String },
+    /// A chunk index is past the end of the chunk list.
+    IndexOutOfRange { index: usize, len: usize },
+    /// A chunk position could not be parsed.
+    InvalidPosition { position: String },
+    /// Chunk data looks like an envelope but can not be parsed.
+    InvalidEnvelope { reason: String },
+    /// A key could not be parsed or used.
+    InvalidKey { reason: String },
+    /// The message is encrypted and no key to open it was given.
+    KeyRequired { scheme: &'static str },
+    /// The key is wrong or the encrypted message was tampered with.
+    DecryptionFailed,
+    /// A seal chunk can not be parsed, or the image no longer matches its seal.
+    InvalidSeal { reason: String },
+    /// Chunks failed signature verification against a trusted key.
+    InvalidSignature { count: usize },
+    /// A compressed message is corrupt or larger than allowed.
+    Decompression { reason: String },
+    /// The parts of a split message can not be put back together.
+    InvalidParts { message_id: u64, reason: String },
+    /// The `IDAT` chunks do not hold valid image data for the `IHDR`.
+    InvalidImageData { reason: String },
+    /// The image is valid but can not be used for this operation.
+    UnsupportedImage { reason: String },
+    /// A channel selection could not be parsed.
+    InvalidChannels { channels: String },
+    /// A pixel export format could not be parsed.
+    InvalidPixelFormat { format: String },
+    /// The message does not fit in the pixels of the image.
+    CapacityExceeded { needed: usize, available: usize },
+    /// No message is hidden in the pixels of the image.
+    MessageNotFound { reason: String },
+    /// Chunk data was expected to be text but is not valid UTF-8.
+    InvalidUtf8(FromUtf8Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadSignature => write!(f, "Signature is not valid"),
+            Error::ChunkTooLong { offset, length } => write!(
+                f,
+                "Chunk at byte {} is too long ({} > 2^31 - 1)",
+                offset, length
+            ),
+            Error::TruncatedChunk { offset } => write!(f, "Chunk at byte {} is truncated", offset),
+            Error::CrcMismatch { offset, expected, actual } => write!(
+                f,
+                "Invalid crc for chunk at byte {}. Expected {}, but got {}.",
+                offset, expected, actual
+            ),
+            Error::InvalidChunkType { chunk_type } => write!(
+                f,
+                "Bad chunk type \"{}\": it should be 4 ASCII letters",
+                chunk_type
+            ),
+            Error::ChunkNotFound { chunk_type } => write!(f, "No chunk of type {} found", chunk_type),
+            Error::InvalidHeader { reason } => write!(f, "Bad IHDR: {}", reason),
+            Error::IndexOutOfRange { index, len } => write!(
+                f,
+                "Chunk index {} is out of range for a PNG with {} chunks",
+                index, len
+            ),
+            Error::InvalidPosition { position } => write!(
+                f,
+                "Bad position {:?}: expected before-iend, after-ihdr, before-idat or an index",
+                position
+            ),
+            Error::InvalidEnvelope { reason } => write!(f, "Bad message envelope: {}", reason),
+            Error::InvalidKey { reason } => write!(f, "Bad key: {}", reason),
+            Error::KeyRequired { scheme } => {
+                write!(f, "Message is encrypted with {}, but no key was given", scheme)
+            }
+            Error::DecryptionFailed => {
+                write!(f, "Could not decrypt message: wrong key or tampered data")
+            }
+            Error::InvalidSeal { reason } => write!(f, "Bad seal: {}", reason),
+            Error::InvalidSignature { count } => {
+                write!(f, "{} chunk(s) have an invalid signature", count)
+            }
+            Error::Decompression { reason } => write!(f, "Could not decompress message: {}", reason),
+            Error::InvalidParts { message_id, reason } => {
+                write!(f, "Can not reassemble message {:016x}: {}", message_id, reason)
+            }
+            Error::InvalidImageData { reason } => write!(f, "Bad image data: {}", reason),
+            Error::UnsupportedImage { reason } => write!(f, "Unsupported image: {}", reason),
+            Error::InvalidChannels { channels } => write!(
+                f,
+                "Bad channels {:?}: expected letters from rgba",
+                channels
+            ),
+            Error::InvalidPixelFormat { format } => write!(
+                f,
+                "Bad pixel format {:?}: expected raw, pnm, pgm, ppm or pam",
+                format
+            ),
+            Error::CapacityExceeded { needed, available } => write!(
+                f,
+                "Message needs {} bytes but the image only holds {}",
+                needed, available
+            ),
+            Error::MessageNotFound { reason } => write!(f, "No hidden message: {}", reason),
+            Error::InvalidUtf8(e) => write!(f, "Chunk data is not valid UTF-8: {}", e),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidUtf8(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(e: FromUtf8Error) -> Self {
+        Error::InvalidUtf8(e)
+    }
+}
@@ -0,0 +1,171 @@
+//! PNG scanline filters.
+//!
+//! Each scanline of the image data starts with a filter type byte. Filters predict every
+//! byte from its neighbours: `a` is the byte one pixel to the left, `b` the byte above
+//! and `c` the byte above and to the left, all 0 outside the image.
+//!
+//! When writing, [`filter_adaptive`] picks a filter per row with the heuristic the PNG
+//! specification recommends: the one whose output has the smallest sum of absolute
+//! values, reading each byte as signed.
+
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+}
+
+impl TryFrom<u8> for FilterType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(FilterType::None),
+            1 => Ok(FilterType::Sub),
+            2 => Ok(FilterType::Up),
+            3 => Ok(FilterType::Average),
+            4 => Ok(FilterType::Paeth),
+            _ => Err(Error::InvalidImageData {
+                reason: format!("unknown filter type {}", value),
+            }),
+        }
+    }
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 5] = [
+        FilterType::None,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Average,
+        FilterType::Paeth,
+    ];
+
+    pub fn code(self) -> u8 {
+        match self {
+            FilterType::None => 0,
+            FilterType::Sub => 1,
+            FilterType::Up => 2,
+            FilterType::Average => 3,
+            FilterType::Paeth => 4,
+        }
+    }
+
+    fn predict(self, a: u8, b: u8, c: u8) -> u8 {
+        match self {
+            FilterType::None => 0,
+            FilterType::Sub => a,
+            FilterType::Up => b,
+            FilterType::Average => ((a as u16 + b as u16) / 2) as u8,
+            FilterType::Paeth => paeth(a, b, c),
+        }
+    }
+}
+
+/// Reverses `filter` on `row` in place. `previous` is the unfiltered row above, or all
+/// zeros for the first row, and `stride` is [`crate::Ihdr::filter_stride`].
+pub fn unfilter(filter: FilterType, row: &mut [u8], previous: &[u8], stride: usize) {
+    for i in 0..row.len() {
+        let a = if i >= stride { row[i - stride] } else { 0 };
+        let c = if i >= stride { previous[i - stride] } else { 0 };
+        row[i] = row[i].wrapping_add(filter.predict(a, previous[i], c));
+    }
+}
+
+/// Applies `filter` to the unfiltered `row`, writing the result to `out`.
+pub fn filter(filter: FilterType, row: &[u8], previous: &[u8], stride: usize, out: &mut [u8]) {
+    for i in 0..row.len() {
+        let a = if i >= stride { row[i - stride] } else { 0 };
+        let c = if i >= stride { previous[i - stride] } else { 0 };
+        out[i] = row[i].wrapping_sub(filter.predict(a, previous[i], c));
+    }
+}
+
+/// Filters `row` into `out` with whichever filter gives the smallest sum of absolute
+/// differences, and returns that filter.
+pub fn filter_adaptive(row: &[u8], previous: &[u8], stride: usize, out: &mut [u8]) -> FilterType {
+    let mut candidate = vec![0; row.len()];
+    let mut best = (FilterType::None, u64::MAX);
+    for filter_type in FilterType::ALL {
+        filter(filter_type, row, previous, stride, &mut candidate);
+        let score = candidate.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum();
+        if score < best.1 {
+            best = (filter_type, score);
+            out.copy_from_slice(&candidate);
+        }
+    }
+    best.0
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_then_unfilter() {
+        let previous = [10, 200, 30, 40, 250, 60];
+        let row = [255, 0, 17, 99, 3, 128];
+        for filter_type in FilterType::ALL {
+            let mut filtered = [0; 6];
+            filter(filter_type, &row, &previous, 2, &mut filtered);
+            unfilter(filter_type, &mut filtered, &previous, 2);
+            assert_eq!(filtered, row, "{:?}", filter_type);
+        }
+    }
+
+    #[test]
+    fn test_sub_and_up() {
+        let mut out = [0; 4];
+        filter(FilterType::Sub, &[1, 2, 4, 8], &[0; 4], 1, &mut out);
+        assert_eq!(out, [1, 1, 2, 4]);
+        filter(FilterType::Up, &[1, 2, 4, 8], &[1, 1, 1, 1], 1, &mut out);
+        assert_eq!(out, [0, 1, 3, 7]);
+    }
+
+    #[test]
+    fn test_adaptive_selection() {
+        let mut out = [0; 6];
+        // A ramp is cheapest as differences from the left.
+        let ramp = [10, 20, 30, 40, 50, 60];
+        assert_eq!(filter_adaptive(&ramp, &[0; 6], 1, &mut out), FilterType::Sub);
+        assert_eq!(out, [10, 10, 10, 10, 10, 10]);
+        // A row that repeats the one above is cheapest as differences from above.
+        let noise = [200, 3, 170, 45, 99, 250];
+        assert_eq!(filter_adaptive(&noise, &noise, 1, &mut out), FilterType::Up);
+        assert_eq!(out, [0; 6]);
+        // Ties go to the simplest filter.
+        assert_eq!(filter_adaptive(&[0; 6], &[0; 6], 1, &mut out), FilterType::None);
+    }
+
+    #[test]
+    fn test_paeth_predictor() {
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(5, 5, 5), 5);
+        assert_eq!(paeth(0, 255, 255), 0);
+    }
+
+    #[test]
+    fn test_unknown_filter_type() {
+        assert_eq!(FilterType::try_from(4).unwrap().code(), 4);
+        assert!(FilterType::try_from(5).is_err());
+    }
+}
@@ -0,0 +1,584 @@
+//! Decoding and re-encoding the image data stored in `IDAT` chunks.
+
+use std::io::{Read, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use crate::chunk::{Chunk, MAXIMUM_LENGTH};
+use crate::chunk_type::ChunkType;
+use crate::filter::{self, FilterType};
+use crate::ihdr::{Ihdr, Interlace};
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// The size of the `IDAT` chunks written by [`Scanlines::write_to`], as used by libpng.
+pub const IDAT_CHUNK_SIZE: usize = 8192;
+
+/// Origin and spacing of the seven Adam7 passes: `(x, y, dx, dy)`.
+const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// A reduced image the image data is stored as: the whole image when it is not
+/// interlaced, or one of the Adam7 passes. The pass holds the pixels at `x + i * dx`,
+/// `y + j * dy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pass {
+    pub x: u32,
+    pub y: u32,
+    pub dx: u32,
+    pub dy: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The passes the image data of `ihdr` is stored as, in order. Empty passes are left out:
+/// they have no scanlines at all.
+pub fn passes(ihdr: &Ihdr) -> Vec<Pass> {
+    let origins: &[(u32, u32, u32, u32)] = match ihdr.interlace {
+        Interlace::None => &[(0, 0, 1, 1)],
+        Interlace::Adam7 => &ADAM7,
+    };
+    origins
+        .iter()
+        .map(|&(x, y, dx, dy)| Pass {
+            x,
+            y,
+            dx,
+            dy,
+            width: ihdr.width.saturating_sub(x).div_ceil(dx),
+            height: ihdr.height.saturating_sub(y).div_ceil(dy),
+        })
+        .filter(|pass| pass.width > 0 && pass.height > 0)
+        .collect()
+}
+
+/// Concatenates and inflates the `IDAT` chunks of `png`. The result must be exactly as
+/// long as the `IHDR` geometry says: one filter byte plus [`Ihdr::row_bytes`] per row of
+/// every pass.
+pub fn inflate(png: &Png) -> Result<Vec<u8>> {
+    let ihdr = png.header_info()?;
+    inflate_exact(png, expected_size(&ihdr)?)
+}
+
+/// The filtered scanlines of an image, as stored: each row starts with its filter type
+/// byte, and interlaced images hold the rows of each pass in turn.
+pub struct RawScanlines {
+    ihdr: Ihdr,
+    data: Vec<u8>,
+}
+
+impl RawScanlines {
+    pub fn read(png: &Png) -> Result<Self> {
+        let ihdr = png.header_info()?;
+        let data = inflate(png)?;
+        Ok(RawScanlines { ihdr, data })
+    }
+
+    pub fn ihdr(&self) -> &Ihdr {
+        &self.ihdr
+    }
+
+    /// The `n`-th stored row as its filter type byte and filtered bytes, or `None` past
+    /// the last row.
+    pub fn row(&self, n: usize) -> Option<(u8, &[u8])> {
+        self.rows().nth(n)
+    }
+
+    /// Every stored row, pass after pass.
+    pub fn rows(&self) -> impl Iterator<Item = (u8, &[u8])> {
+        let ihdr = self.ihdr;
+        let mut rest = self.data.as_slice();
+        passes(&ihdr)
+            .into_iter()
+            .flat_map(move |pass| (0..pass.height).map(move |_| ihdr.row_bytes(pass.width) + 1))
+            .map(move |length| {
+                let (line, tail) = rest.split_at(length);
+                rest = tail;
+                (line[0], &line[1..])
+            })
+    }
+
+    /// Reverses the filters and puts the passes of interlaced images back together,
+    /// failing on unknown filter types.
+    pub fn unfilter(&self) -> Result<Scanlines> {
+        let ihdr = self.ihdr;
+        let row_bytes = ihdr.row_bytes(ihdr.width);
+        let mut filters = Vec::new();
+        let mut pixels = vec![0; ihdr.height as usize * row_bytes];
+        let mut rows = self.rows();
+        for pass in passes(&ihdr) {
+            let pass_bytes = ihdr.row_bytes(pass.width);
+            let mut previous = vec![0; pass_bytes];
+            let mut line = vec![0; pass_bytes];
+            for j in 0..pass.height {
+                let (filter_type, filtered) = rows.next().expect("inflate checked the length");
+                let filter_type = FilterType::try_from(filter_type)?;
+                line.copy_from_slice(filtered);
+                filter::unfilter(filter_type, &mut line, &previous, ihdr.filter_stride());
+                let y = (pass.y + j * pass.dy) as usize;
+                let row = &mut pixels[y * row_bytes..(y + 1) * row_bytes];
+                if pass.dx == 1 {
+                    row.copy_from_slice(&line);
+                } else {
+                    for i in 0..pass.width as usize {
+                        let x = pass.x as usize + i * pass.dx as usize;
+                        copy_pixel(&line, i, row, x, ihdr.bits_per_pixel());
+                    }
+                }
+                std::mem::swap(&mut previous, &mut line);
+                filters.push(filter_type);
+            }
+        }
+        Ok(Scanlines { ihdr, filters, pixels })
+    }
+}
+
+/// How [`Scanlines::write_with`] filters each row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filtering {
+    /// The filter with the smallest sum of absolute differences, row by row.
+    Adaptive,
+    /// The filter the row was read with. Rows without one, after the interlace method
+    /// changed, are filtered adaptively.
+    Original,
+}
+
+/// The unfiltered pixels of an image, de-interlaced, with the filter each stored row
+/// used.
+pub struct Scanlines {
+    ihdr: Ihdr,
+    filters: Vec<FilterType>,
+    pixels: Vec<u8>,
+}
+
+impl Scanlines {
+    /// Inflates and unfilters the image data of `png`.
+    pub fn read(png: &Png) -> Result<Self> {
+        RawScanlines::read(png)?.unfilter()
+    }
+
+    pub fn ihdr(&self) -> &Ihdr {
+        &self.ihdr
+    }
+
+    /// Changes how the image is stored when written. The pixels stay the same.
+    pub fn set_interlace(&mut self, interlace: Interlace) {
+        if interlace != self.ihdr.interlace {
+            self.ihdr.interlace = interlace;
+            self.filters.clear();
+        }
+    }
+
+    /// The filter each stored row used, pass after pass for interlaced images.
+    pub fn filters(&self) -> &[FilterType] {
+        &self.filters
+    }
+
+    /// The unfiltered rows of the full image, one after the other, [`Ihdr::row_bytes`]
+    /// each.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+
+    /// The unfiltered bytes of row `y`, or `None` past the last row.
+    pub fn row(&self, y: u32) -> Option<&[u8]> {
+        if y >= self.ihdr.height {
+            return None;
+        }
+        let row_bytes = self.ihdr.row_bytes(self.ihdr.width);
+        let start = y as usize * row_bytes;
+        Some(&self.pixels[start..start + row_bytes])
+    }
+
+    /// Sample `channel` of the pixel at (`x`, `y`), or `None` outside the image. Samples
+    /// are returned as stored: palette indices for indexed images, and not scaled to
+    /// 16 bits for lower bit depths.
+    pub fn sample(&self, x: u32, y: u32, channel: u8) -> Option<u16> {
+        if x >= self.ihdr.width || channel >= self.ihdr.color_type.channels() {
+            return None;
+        }
+        let row = self.row(y)?;
+        let depth = self.ihdr.bit_depth as usize;
+        let bit = (x as usize * self.ihdr.color_type.channels() as usize + channel as usize) * depth;
+        Some(match depth {
+            16 => u16::from_be_bytes([row[bit / 8], row[bit / 8 + 1]]),
+            8 => row[bit / 8] as u16,
+            _ => ((row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8) as u16,
+        })
+    }
+
+    /// Filters and compresses the rows, choosing filters adaptively, and replaces the
+    /// image data of `png`, along with its `IHDR` if the interlace method changed.
+    pub fn write_to(&self, png: &mut Png) -> Result<()> {
+        self.write_with(png, Filtering::Adaptive)
+    }
+
+    /// Like [`Scanlines::write_to`], with a choice of filters.
+    pub fn write_with(&self, png: &mut Png, filtering: Filtering) -> Result<()> {
+        let ihdr = self.ihdr;
+        let row_bytes = ihdr.row_bytes(ihdr.width);
+        let stride = ihdr.filter_stride();
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut originals = self.filters.iter();
+        for pass in passes(&ihdr) {
+            let pass_bytes = ihdr.row_bytes(pass.width);
+            let mut previous = vec![0; pass_bytes];
+            let mut current = vec![0; pass_bytes];
+            let mut line = vec![0; pass_bytes];
+            for j in 0..pass.height {
+                let y = (pass.y + j * pass.dy) as usize;
+                let row = &self.pixels[y * row_bytes..(y + 1) * row_bytes];
+                if pass.dx == 1 {
+                    current.copy_from_slice(row);
+                } else {
+                    for i in 0..pass.width as usize {
+                        let x = pass.x as usize + i * pass.dx as usize;
+                        copy_pixel(row, x, &mut current, i, ihdr.bits_per_pixel());
+                    }
+                }
+                let filter_type = match (filtering, originals.next()) {
+                    (Filtering::Original, Some(&original)) => {
+                        filter::filter(original, &current, &previous, stride, &mut line);
+                        original
+                    }
+                    _ => filter::filter_adaptive(&current, &previous, stride, &mut line),
+                };
+                encoder.write_all(&[filter_type.code()])?;
+                encoder.write_all(&line)?;
+                std::mem::swap(&mut previous, &mut current);
+            }
+        }
+        let chunks = idat_chunks(&encoder.finish()?, IDAT_CHUNK_SIZE)?;
+        if png.header_info()? != ihdr {
+            png.set_header(&ihdr)?;
+        }
+        png.replace_image_data(chunks)
+    }
+}
+
+/// Inflates the image data of `png` and deflates it again at `level`, from 0 (stored) to
+/// 9 (smallest), in `IDAT` chunks of at most `chunk_size` bytes. The filtered scanlines
+/// are kept as they are and every other chunk is left alone.
+pub fn recompress(png: &mut Png, level: u32, chunk_size: usize) -> Result<()> {
+    let data = inflate(png)?;
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::new(level.min(9)));
+    encoder.write_all(&data)?;
+    let chunks = idat_chunks(&encoder.finish()?, chunk_size)?;
+    png.replace_image_data(chunks)
+}
+
+/// Splits a zlib stream into `IDAT` chunks of at most `chunk_size` bytes.
+fn idat_chunks(compressed: &[u8], chunk_size: usize) -> Result<Vec<Chunk>> {
+    let idat = ChunkType::try_from(*b"IDAT")?;
+    Ok(compressed
+        .chunks(chunk_size.clamp(1, MAXIMUM_LENGTH as usize))
+        .map(|data| Chunk::new(idat.clone(), data.to_vec()))
+        .collect())
+}
+
+/// Copies pixel `from_x` of `from` to pixel `to_x` of `to`, for pixels of `bits` bits.
+/// Pixels under 8 bits share bytes, most significant bits first.
+fn copy_pixel(from: &[u8], from_x: usize, to: &mut [u8], to_x: usize, bits: usize) {
+    if bits >= 8 {
+        let bytes = bits / 8;
+        to[to_x * bytes..(to_x + 1) * bytes].copy_from_slice(&from[from_x * bytes..(from_x + 1) * bytes]);
+        return;
+    }
+    let mask = (1u8 << bits) - 1;
+    let from_shift = 8 - bits - from_x * bits % 8;
+    let value = (from[from_x * bits / 8] >> from_shift) & mask;
+    let to_shift = 8 - bits - to_x * bits % 8;
+    let byte = &mut to[to_x * bits / 8];
+    *byte = (*byte & !(mask << to_shift)) | (value << to_shift);
+}
+
+/// The inflated size of the image data, failing for images too large to hold in memory.
+fn expected_size(ihdr: &Ihdr) -> Result<usize> {
+    passes(ihdr)
+        .iter()
+        .try_fold(0usize, |total, pass| {
+            (ihdr.row_bytes(pass.width) + 1)
+                .checked_mul(pass.height as usize)
+                .and_then(|size| total.checked_add(size))
+        })
+        .filter(|&size| size <= isize::MAX as usize)
+        .ok_or_else(|| Error::UnsupportedImage {
+            reason: format!("{}x{} is too large", ihdr.width, ihdr.height),
+        })
+}
+
+/// Concatenates and inflates the `IDAT` chunks of `png`, which must hold exactly
+/// `expected` bytes once inflated. Memory grows with the actual output rather than being
+/// reserved up front, so a small file declaring huge dimensions fails cheaply.
+fn inflate_exact(png: &Png, expected: usize) -> Result<Vec<u8>> {
+    let compressed: Vec<u8> = png.chunks_by_type("IDAT").flat_map(|c| c.data().iter().copied()).collect();
+    if compressed.is_empty() {
+        return Err(Error::ChunkNotFound { chunk_type: "IDAT".to_string() });
+    }
+    // Deflate can not expand data more than about 1032 times.
+    let mut data = Vec::with_capacity(expected.min(compressed.len().saturating_mul(1032)));
+    ZlibDecoder::new(compressed.as_slice())
+        .take(expected as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| Error::InvalidImageData { reason: format!("bad zlib stream: {}", e) })?;
+    if data.len() != expected {
+        return Err(Error::InvalidImageData {
+            reason: format!("expected {} bytes of scanlines, got {}", expected, data.len()),
+        });
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+
+    /// An 8-bit RGB gradient stored with every filter type in turn.
+    pub(crate) fn testing_image(width: u32, height: u32) -> Png {
+        let ihdr = Ihdr {
+            width,
+            height,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            compression_method: 0,
+            filter_method: 0,
+            interlace: Interlace::None,
+        };
+        let mut png = empty_image(ihdr, Vec::new());
+        let filters = FilterType::ALL;
+        let scanlines = Scanlines {
+            ihdr,
+            filters: (0..height as usize).map(|y| filters[y % filters.len()]).collect(),
+            pixels: (0..ihdr.row_bytes(width) * height as usize).map(|i| (i * 7 % 251) as u8).collect(),
+        };
+        scanlines.write_with(&mut png, Filtering::Original).unwrap();
+        png
+    }
+
+    /// An image holding the unfiltered `pixels`, with `chunks` such as `PLTE` before its
+    /// image data.
+    pub(crate) fn encode_image(ihdr: Ihdr, pixels: Vec<u8>, chunks: Vec<Chunk>) -> Png {
+        let mut png = empty_image(ihdr, chunks);
+        let scanlines = Scanlines { ihdr, filters: Vec::new(), pixels };
+        scanlines.write_to(&mut png).unwrap();
+        png
+    }
+
+    /// `ihdr` and `chunks` followed by an empty `IDAT` and `IEND`, for the image data to
+    /// be written into.
+    fn empty_image(ihdr: Ihdr, chunks: Vec<Chunk>) -> Png {
+        let mut all = vec![ihdr.to_chunk()];
+        all.extend(chunks);
+        all.push(Chunk::new(ChunkType::try_from(*b"IDAT").unwrap(), Vec::new()));
+        all.push(Chunk::new(ChunkType::try_from(*b"IEND").unwrap(), Vec::new()));
+        Png::from_chunks(all)
+    }
+
+    #[test]
+    fn test_read_then_write() {
+        let png = testing_image(40, 30);
+        let scanlines = Scanlines::read(&png).unwrap();
+        assert_eq!(scanlines.pixels().len(), 40 * 30 * 3);
+        assert_eq!(scanlines.pixels()[..3], [0, 7, 14]);
+
+        let mut copy = Png::from_chunks(png.chunks().to_vec());
+        scanlines.write_with(&mut copy, Filtering::Original).unwrap();
+        let reread = Scanlines::read(&copy).unwrap();
+        assert_eq!(reread.pixels(), scanlines.pixels());
+        assert_eq!(reread.filters(), scanlines.filters());
+    }
+
+    #[test]
+    fn test_adaptive_write() {
+        let png = testing_image(40, 30);
+        let scanlines = Scanlines::read(&png).unwrap();
+        let mut copy = Png::from_chunks(png.chunks().to_vec());
+        scanlines.write_to(&mut copy).unwrap();
+        let reread = Scanlines::read(&copy).unwrap();
+        assert_eq!(reread.pixels(), scanlines.pixels());
+        // The gradient steps by the same amount from pixel to pixel.
+        assert!(reread.filters().iter().all(|&f| f == FilterType::Sub));
+    }
+
+    #[test]
+    fn test_raw_rows() {
+        let png = testing_image(8, 6);
+        let raw = RawScanlines::read(&png).unwrap();
+        let filters: Vec<u8> = raw.rows().map(|(filter_type, _)| filter_type).collect();
+        assert_eq!(filters, [0, 1, 2, 3, 4, 0]);
+        assert_eq!(raw.row(1).unwrap().1.len(), 8 * 3);
+        assert!(raw.row(6).is_none());
+        assert_eq!(inflate(&png).unwrap().len(), 6 * (8 * 3 + 1));
+    }
+
+    #[test]
+    fn test_adam7_passes() {
+        let png = testing_image(10, 9);
+        let mut ihdr = png.header_info().unwrap();
+        assert_eq!(passes(&ihdr).len(), 1);
+        ihdr.interlace = Interlace::Adam7;
+        let sizes: Vec<(u32, u32)> = passes(&ihdr).iter().map(|p| (p.width, p.height)).collect();
+        assert_eq!(sizes, [(2, 2), (1, 2), (3, 1), (2, 3), (5, 2), (5, 5), (10, 4)]);
+        let pixels: u32 = sizes.iter().map(|(w, h)| w * h).sum();
+        assert_eq!(pixels, 10 * 9);
+
+        ihdr.width = 1;
+        ihdr.height = 1;
+        assert_eq!(passes(&ihdr).len(), 1);
+    }
+
+    #[test]
+    fn test_interlace_round_trip() {
+        let png = testing_image(13, 11);
+        let mut scanlines = Scanlines::read(&png).unwrap();
+        scanlines.set_interlace(Interlace::Adam7);
+        let mut interlaced = Png::from_chunks(png.chunks().to_vec());
+        scanlines.write_to(&mut interlaced).unwrap();
+        assert_eq!(interlaced.header_info().unwrap().interlace, Interlace::Adam7);
+
+        let mut reread = Scanlines::read(&interlaced).unwrap();
+        assert_eq!(reread.pixels(), scanlines.pixels());
+        assert_eq!(RawScanlines::read(&interlaced).unwrap().rows().count(), 2 + 2 + 1 + 3 + 3 + 6 + 5);
+
+        reread.set_interlace(Interlace::None);
+        reread.write_with(&mut interlaced, Filtering::Original).unwrap();
+        assert_eq!(interlaced.header_info().unwrap(), png.header_info().unwrap());
+        assert_eq!(Scanlines::read(&interlaced).unwrap().pixels(), scanlines.pixels());
+    }
+
+    #[test]
+    fn test_interlaced_low_bit_depth() {
+        let ihdr = Ihdr {
+            width: 11,
+            height: 5,
+            bit_depth: 2,
+            color_type: ColorType::Grayscale,
+            compression_method: 0,
+            filter_method: 0,
+            interlace: Interlace::Adam7,
+        };
+        // Padding bits at the end of each row stay zero.
+        let pixels: Vec<u8> = (0..5).flat_map(|y| [0b00_01_10_11, 0b10_01_00_11, y << 2]).collect();
+        let png = encode_image(ihdr, pixels.clone(), Vec::new());
+        let reread = Scanlines::read(&png).unwrap();
+        assert_eq!(reread.pixels(), pixels);
+        assert_eq!(reread.sample(3, 0, 0), Some(3));
+        assert_eq!(reread.sample(10, 4, 0), Some(0));
+    }
+
+    #[test]
+    fn test_recompress() {
+        let png = testing_image(30, 30);
+        let pixels = Scanlines::read(&png).unwrap().pixels().to_vec();
+        let raw = inflate(&png).unwrap();
+
+        let mut stored = Png::from_chunks(png.chunks().to_vec());
+        recompress(&mut stored, 0, 100).unwrap();
+        let mut smallest = Png::from_chunks(png.chunks().to_vec());
+        recompress(&mut smallest, 9, usize::MAX).unwrap();
+        for recompressed in [&stored, &smallest] {
+            assert_eq!(inflate(recompressed).unwrap(), raw);
+            assert_eq!(Scanlines::read(recompressed).unwrap().pixels(), pixels);
+            assert_eq!(recompressed.chunks()[0].as_bytes(), png.chunks()[0].as_bytes());
+            assert_eq!(recompressed.chunks().last().unwrap().chunk_type().to_string(), "IEND");
+        }
+        assert!(stored.chunks_by_type("IDAT").all(|chunk| chunk.length() <= 100));
+        assert!(stored.chunks_by_type("IDAT").count() > 1);
+        assert_eq!(smallest.chunks_by_type("IDAT").count(), 1);
+        assert!(smallest.as_bytes().len() < stored.as_bytes().len());
+    }
+
+    #[test]
+    fn test_bounds_checks() {
+        let png = testing_image(8, 6);
+        let scanlines = Scanlines::read(&png).unwrap();
+        assert_eq!(scanlines.row(5).unwrap().len(), 24);
+        assert!(scanlines.row(6).is_none());
+        assert_eq!(scanlines.sample(1, 0, 2), Some(35));
+        assert_eq!(scanlines.sample(7, 5, 2), scanlines.pixels().last().map(|&b| b as u16));
+        assert!(scanlines.sample(8, 0, 0).is_none());
+        assert!(scanlines.sample(0, 6, 0).is_none());
+        assert!(scanlines.sample(0, 0, 3).is_none());
+    }
+
+    #[test]
+    fn test_low_bit_depth_samples() {
+        let ihdr = Ihdr {
+            width: 3,
+            height: 1,
+            bit_depth: 2,
+            color_type: ColorType::Grayscale,
+            compression_method: 0,
+            filter_method: 0,
+            interlace: Interlace::None,
+        };
+        let scanlines = Scanlines { ihdr, filters: vec![FilterType::None], pixels: vec![0b1101_1000] };
+        assert_eq!(scanlines.sample(0, 0, 0), Some(3));
+        assert_eq!(scanlines.sample(1, 0, 0), Some(1));
+        assert_eq!(scanlines.sample(2, 0, 0), Some(2));
+        assert!(scanlines.sample(3, 0, 0).is_none());
+    }
+
+    #[test]
+    fn test_huge_dimensions() {
+        let png = testing_image(4, 4);
+        let mut chunks = png.chunks().to_vec();
+        let mut ihdr = png.header_info().unwrap();
+        ihdr.width = (1 << 31) - 1;
+        ihdr.height = (1 << 31) - 1;
+        chunks[0] = ihdr.to_chunk();
+        let result = Scanlines::read(&Png::from_chunks(chunks.clone()));
+        assert!(matches!(result, Err(Error::UnsupportedImage { .. })));
+
+        ihdr.width = 1 << 20;
+        ihdr.height = 1 << 10;
+        chunks[0] = ihdr.to_chunk();
+        let result = Scanlines::read(&Png::from_chunks(chunks));
+        assert!(matches!(result, Err(Error::InvalidImageData { .. })));
+    }
+
+    #[test]
+    fn test_huge_dimensions_capacity() {
+        use crate::lsb::{self, Channels};
+        use std::str::FromStr;
+
+        let mut ihdr = testing_image(4, 4).header_info().unwrap();
+        ihdr.width = (1 << 31) - 1;
+        ihdr.height = (1 << 31) - 1;
+        ihdr.color_type = ColorType::Rgba;
+        ihdr.bit_depth = 16;
+        let channels = Channels::from_str("rgba").unwrap();
+        let bytes = ((1u64 << 31) - 1).pow(2) / 2 - 12;
+        assert_eq!(lsb::theoretical_capacity(&ihdr, channels, 1), Some(bytes));
+        assert_eq!(lsb::theoretical_capacity(&ihdr, channels, 15), None);
+        match lsb::capacity(&ihdr, channels) {
+            Ok(capacity) => assert_eq!(capacity as u64, bytes),
+            Err(e) => assert!(usize::BITS < 64 && matches!(e, Error::UnsupportedImage { .. })),
+        }
+    }
+
+    #[test]
+    fn test_rejects_truncated_data() {
+        let png = testing_image(10, 10);
+        let mut chunks = png.chunks().to_vec();
+        let idat = &chunks[1];
+        chunks[1] = Chunk::new(idat.chunk_type().clone(), idat.data()[..idat.data().len() / 2].to_vec());
+        assert!(matches!(
+            Scanlines::read(&Png::from_chunks(chunks)),
+            Err(Error::InvalidImageData { .. })
+        ));
+    }
+}
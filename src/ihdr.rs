@@ -0,0 +1,297 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::{Error, Result};
+
+const MAXIMUM_DIMENSION: u32 = (1 << 31) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(Error::InvalidHeader {
+                reason: format!("Unknown color type {}", value),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ColorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale+alpha",
+            ColorType::Rgba => "RGBA",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ColorType {
+    /// The value stored in the IHDR chunk.
+    pub fn code(&self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+    /// Number of samples in each pixel.
+    pub fn channels(&self) -> u8 {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+    /// Bit depths the spec allows for this color type.
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interlace {
+    None,
+    Adam7,
+}
+
+impl fmt::Display for Interlace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interlace::None => write!(f, "non-interlaced"),
+            Interlace::Adam7 => write!(f, "Adam7 interlaced"),
+        }
+    }
+}
+
+/// The image header stored in the `IHDR` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace: Interlace,
+}
+
+impl Ihdr {
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() as usize * self.bit_depth as usize
+    }
+    /// Distance in bytes between a byte and the matching byte of the previous pixel, as
+    /// used by scanline filters. At least 1 for images with less than 8 bits per pixel.
+    pub fn filter_stride(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+    /// Encodes the header as an `IHDR` chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = self.width.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.extend_from_slice(&[
+            self.bit_depth,
+            self.color_type.code(),
+            self.compression_method,
+            self.filter_method,
+            match self.interlace {
+                Interlace::None => 0,
+                Interlace::Adam7 => 1,
+            },
+        ]);
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data)
+    }
+    /// Bytes in one scanline of an image `width` pixels wide, without the filter byte.
+    pub fn row_bytes(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        if &chunk.chunk_type().bytes() != b"IHDR" {
+            return Err(Error::InvalidHeader {
+                reason: format!("Expected an IHDR chunk, but got {}", chunk.chunk_type()),
+            });
+        }
+        let data = chunk.data();
+        if data.len() != 13 {
+            return Err(Error::InvalidHeader {
+                reason: format!("IHDR should have 13 bytes, but got {}", data.len()),
+            });
+        }
+
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        for (name, value) in [("Width", width), ("Height", height)] {
+            if value == 0 || value > MAXIMUM_DIMENSION {
+                return Err(Error::InvalidHeader {
+                    reason: format!("{} should be between 1 and 2^31 - 1, but got {}", name, value),
+                });
+            }
+        }
+
+        let bit_depth = data[8];
+        let color_type = ColorType::try_from(data[9])?;
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(Error::InvalidHeader {
+                reason: format!("Bit depth {} is not allowed for {} images", bit_depth, color_type),
+            });
+        }
+
+        let compression_method = data[10];
+        if compression_method != 0 {
+            return Err(Error::InvalidHeader {
+                reason: format!("Unknown compression method {}", compression_method),
+            });
+        }
+        let filter_method = data[11];
+        if filter_method != 0 {
+            return Err(Error::InvalidHeader {
+                reason: format!("Unknown filter method {}", filter_method),
+            });
+        }
+        let interlace = match data[12] {
+            0 => Interlace::None,
+            1 => Interlace::Adam7,
+            other => {
+                return Err(Error::InvalidHeader {
+                    reason: format!("Unknown interlace method {}", other),
+                })
+            }
+        };
+
+        Ok(Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method,
+            filter_method,
+            interlace,
+        })
+    }
+}
+
+impl fmt::Display for Ihdr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{}, {}-bit {}, {}",
+            self.width, self.height, self.bit_depth, self.color_type, self.interlace
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr_chunk(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Chunk {
+        let data: Vec<u8> = width
+            .to_be_bytes()
+            .iter()
+            .chain(height.to_be_bytes().iter())
+            .chain([bit_depth, color_type, 0, 0, interlace].iter())
+            .copied()
+            .collect();
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data)
+    }
+
+    #[test]
+    fn test_scanline_sizes() {
+        let rgba16 = Ihdr::try_from(&ihdr_chunk(3, 1, 16, 6, 0)).unwrap();
+        assert_eq!(rgba16.filter_stride(), 8);
+        assert_eq!(rgba16.row_bytes(3), 24);
+        let gray1 = Ihdr::try_from(&ihdr_chunk(10, 1, 1, 0, 0)).unwrap();
+        assert_eq!(gray1.filter_stride(), 1);
+        assert_eq!(gray1.row_bytes(10), 2);
+    }
+
+    #[test]
+    fn test_to_chunk_round_trip() {
+        let chunk = ihdr_chunk(640, 480, 16, 2, 1);
+        let ihdr = Ihdr::try_from(&chunk).unwrap();
+        assert_eq!(ihdr.to_chunk().as_bytes(), chunk.as_bytes());
+    }
+
+    #[test]
+    fn test_valid_ihdr() {
+        let ihdr = Ihdr::try_from(&ihdr_chunk(50, 40, 8, 6, 0)).unwrap();
+        assert_eq!(ihdr.width, 50);
+        assert_eq!(ihdr.height, 40);
+        assert_eq!(ihdr.bit_depth, 8);
+        assert_eq!(ihdr.color_type, ColorType::Rgba);
+        assert_eq!(ihdr.interlace, Interlace::None);
+    }
+
+    #[test]
+    fn test_adam7_ihdr() {
+        let ihdr = Ihdr::try_from(&ihdr_chunk(1, 1, 1, 0, 1)).unwrap();
+        assert_eq!(ihdr.interlace, Interlace::Adam7);
+    }
+
+    #[test]
+    fn test_invalid_bit_depth_for_color_type() {
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 4, 2, 0)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 16, 3, 0)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 3, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_invalid_color_type() {
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 8, 5, 0)).is_err());
+    }
+
+    #[test]
+    fn test_zero_dimension() {
+        assert!(Ihdr::try_from(&ihdr_chunk(0, 1, 8, 2, 0)).is_err());
+    }
+
+    #[test]
+    fn test_invalid_interlace() {
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 8, 2, 2)).is_err());
+    }
+
+    #[test]
+    fn test_wrong_chunk_type() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![0; 13]);
+        assert!(Ihdr::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_ihdr_display() {
+        let ihdr = Ihdr::try_from(&ihdr_chunk(50, 40, 8, 6, 0)).unwrap();
+        assert_eq!(ihdr.to_string(), "50x40, 8-bit RGBA, non-interlaced");
+    }
+}
@@ -0,0 +1,267 @@
+//! Key pairs for public-key encryption and signing of messages.
+//!
+//! Keys are stored as a single line of text: a label naming the key kind, a space,
+//! and the standard base64 encoding of the 32 key bytes. Blank lines and lines
+//! starting with `#` are ignored, so key files may carry comments.
+//!
+//! | Label                    | Contents                                 |
+//! |--------------------------|------------------------------------------|
+//! | `pngme-x25519-public`    | X25519 public key, given to senders      |
+//! | `pngme-x25519-secret`    | X25519 secret key, kept by the recipient |
+//! | `pngme-ed25519-public`   | Ed25519 public key, trusted by verifiers |
+//! | `pngme-ed25519-secret`   | Ed25519 secret key, kept by the signer   |
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::OsRng;
+use ed25519_dalek::Signer;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{Error, Result};
+
+const RECIPIENT_LABEL: &str = "pngme-x25519-public";
+const IDENTITY_LABEL: &str = "pngme-x25519-secret";
+const VERIFYING_KEY_LABEL: &str = "pngme-ed25519-public";
+const SIGNING_KEY_LABEL: &str = "pngme-ed25519-secret";
+
+/// The public half of a key pair: messages encrypted for it can only be read with the
+/// matching [`Identity`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recipient(PublicKey);
+
+/// The secret half of a key pair.
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+/// An Ed25519 secret key used to sign messages.
+#[derive(Clone)]
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+/// The Ed25519 public key checking signatures made by a [`SigningKey`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyingKey(ed25519_dalek::VerifyingKey);
+
+impl Recipient {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Recipient(PublicKey::from(bytes))
+    }
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+    pub(crate) fn public_key(&self) -> &PublicKey {
+        &self.0
+    }
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Identity(StaticSecret::random_from_rng(OsRng))
+    }
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Identity(StaticSecret::from(bytes))
+    }
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+    /// The public key to give to senders.
+    pub fn recipient(&self) -> Recipient {
+        Recipient(PublicKey::from(&self.0))
+    }
+    pub(crate) fn secret(&self) -> &StaticSecret {
+        &self.0
+    }
+}
+
+impl SigningKey {
+    pub fn generate() -> Self {
+        SigningKey(ed25519_dalek::SigningKey::generate(&mut OsRng))
+    }
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        SigningKey(ed25519_dalek::SigningKey::from_bytes(&bytes))
+    }
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+    /// The public key to give to verifiers.
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey(self.0.verifying_key())
+    }
+    pub(crate) fn sign(&self, message: &[u8]) -> [u8; 64] {
+        self.0.sign(message).to_bytes()
+    }
+}
+
+impl VerifyingKey {
+    /// Fails if `bytes` is not a point on the curve.
+    pub fn from_bytes(bytes: [u8; 32]) -> Result<Self> {
+        ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+            .map(VerifyingKey)
+            .map_err(|_| invalid_key("not a valid Ed25519 public key"))
+    }
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+    pub(crate) fn verify(&self, message: &[u8], signature: &[u8; 64]) -> bool {
+        let signature = ed25519_dalek::Signature::from_bytes(signature);
+        self.0.verify_strict(message, &signature).is_ok()
+    }
+}
+
+// Never print secret key material by accident.
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Identity({})", self.recipient())
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SigningKey({})", self.verifying_key())
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", encode_key(RECIPIENT_LABEL, &self.to_bytes()))
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", encode_key(IDENTITY_LABEL, &self.to_bytes()))
+    }
+}
+
+impl fmt::Display for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", encode_key(SIGNING_KEY_LABEL, &self.to_bytes()))
+    }
+}
+
+impl fmt::Display for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", encode_key(VERIFYING_KEY_LABEL, &self.to_bytes()))
+    }
+}
+
+impl FromStr for Recipient {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        decode_key(RECIPIENT_LABEL, s).map(Recipient::from_bytes)
+    }
+}
+
+impl FromStr for Identity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        decode_key(IDENTITY_LABEL, s).map(Identity::from_bytes)
+    }
+}
+
+impl FromStr for SigningKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        decode_key(SIGNING_KEY_LABEL, s).map(SigningKey::from_bytes)
+    }
+}
+
+impl FromStr for VerifyingKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        decode_key(VERIFYING_KEY_LABEL, s).and_then(VerifyingKey::from_bytes)
+    }
+}
+
+fn encode_key(label: &str, bytes: &[u8; 32]) -> String {
+    format!("{} {}", label, BASE64.encode(bytes))
+}
+
+fn decode_key(label: &str, text: &str) -> Result<[u8; 32]> {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or_else(|| invalid_key("no key found"))?;
+    let encoded = line
+        .strip_prefix(label)
+        .and_then(|rest| rest.strip_prefix(' '))
+        .ok_or_else(|| invalid_key(&format!("expected a {} key", label)))?;
+    let bytes = BASE64
+        .decode(encoded.trim())
+        .map_err(|e| invalid_key(&format!("bad base64: {}", e)))?;
+    bytes
+        .try_into()
+        .map_err(|_| invalid_key("a key should be 32 bytes long"))
+}
+
+fn invalid_key(reason: &str) -> Error {
+    Error::InvalidKey { reason: reason.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_round_trip() {
+        let identity = Identity::generate();
+        let recipient = identity.recipient();
+
+        let parsed_identity = Identity::from_str(&identity.to_string()).unwrap();
+        let parsed_recipient = Recipient::from_str(&recipient.to_string()).unwrap();
+
+        assert_eq!(parsed_identity.to_bytes(), identity.to_bytes());
+        assert_eq!(parsed_recipient, recipient);
+        assert!(recipient.to_string().starts_with("pngme-x25519-public "));
+    }
+
+    #[test]
+    fn test_signing_key_round_trip() {
+        let signing_key = SigningKey::generate();
+        let verifying_key = signing_key.verifying_key();
+
+        let parsed = SigningKey::from_str(&signing_key.to_string()).unwrap();
+        assert_eq!(parsed.to_bytes(), signing_key.to_bytes());
+        assert_eq!(VerifyingKey::from_str(&verifying_key.to_string()).unwrap(), verifying_key);
+
+        let signature = signing_key.sign(b"message");
+        assert!(verifying_key.verify(b"message", &signature));
+        assert!(!verifying_key.verify(b"massage", &signature));
+    }
+
+    #[test]
+    fn test_comments_are_ignored() {
+        let recipient = Identity::generate().recipient();
+        let text = format!("# created by pngme keygen\n\n{}\n", recipient);
+        assert_eq!(Recipient::from_str(&text).unwrap(), recipient);
+    }
+
+    #[test]
+    fn test_wrong_label() {
+        let identity = Identity::generate();
+        assert!(matches!(
+            Recipient::from_str(&identity.to_string()),
+            Err(Error::InvalidKey { .. })
+        ));
+    }
+
+    #[test]
+    fn test_wrong_length() {
+        let text = format!("{} {}", RECIPIENT_LABEL, BASE64.encode([1, 2, 3]));
+        assert!(Recipient::from_str(&text).is_err());
+    }
+
+    #[test]
+    fn test_debug_hides_secret() {
+        let identity = Identity::generate();
+        let secret = BASE64.encode(identity.to_bytes());
+        assert!(!format!("{:?}", identity).contains(&secret));
+    }
+}
@@ -0,0 +1,31 @@
+//! Hide messages inside PNG files.
+//!
+//! The crate exposes the PNG container types ([`Png`], [`Chunk`], [`ChunkType`], [`Ihdr`])
+//! and the high level operations used by the `pngme` binary in [`operations`]. Pixels
+//! are reached through [`idat`], which inflates and unfilters the image data.
+
+pub mod chunk;
+pub mod chunk_type;
+pub mod compression;
+pub mod envelope;
+pub mod error;
+pub mod filter;
+pub mod idat;
+pub mod ihdr;
+pub mod keys;
+pub mod lsb;
+pub mod operations;
+pub mod parts;
+pub mod pixels;
+pub mod png;
+pub mod reader;
+pub mod seal;
+pub mod writer;
+
+pub use chunk::Chunk;
+pub use chunk_type::ChunkType;
+pub use error::Error;
+pub use ihdr::Ihdr;
+pub use png::Png;
+
+pub type Result<T> = std::result::Result<T, Error>;
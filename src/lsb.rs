@@ -0,0 +1,468 @@
+//! Hiding data in the least significant bits of pixel samples.
+//!
+//! Unlike a custom chunk, data hidden this way survives tools that strip ancillary
+//! chunks, as long as they keep the pixels lossless. The image is decoded, one bit is
+//! written to the low bit of each selected sample in pixel order, and the image data is
+//! re-encoded.
+//!
+//! The hidden bits start with a 12 byte header: [`MAGIC`], the chunk type labelling the
+//! message and its length as a big endian `u32`. Bytes are written most significant bit
+//! first.
+//!
+//! Without a key the bits fill the image from the top, which leaves a trace that
+//! statistical tests such as chi-square analysis pick up in the first rows. With a
+//! [`ScatterKey`] the n-th bit instead goes to the n-th position of a keyed permutation
+//! of every usable bit, so the changes spread over the whole image and the data can only
+//! be found with the same key.
+//!
+//! Only 8 and 16-bit grayscale and truecolor images can hold data: changing the low bit
+//! of a palette index or of a 1, 2 or 4-bit sample visibly changes the pixel.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chunk_type::ChunkType;
+use crate::envelope::{self, Argon2Params, Kdf};
+use crate::idat::Scanlines;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// Marks the start of hidden data.
+pub const MAGIC: [u8; 4] = *b"pmLS";
+const HEADER_LENGTH: usize = 12;
+
+/// The most low bits per sample [`theoretical_capacity`] considers: beyond that the
+/// changes become visible.
+pub const MAX_BITS_PER_SAMPLE: u8 = 4;
+
+/// Salt for [`ScatterKey::derive`]. It has to be fixed: there is nowhere to store a
+/// random one that could be read before the hidden data is found.
+const SCATTER_SALT: &[u8] = b"pngme-lsb-scatter";
+const FEISTEL_ROUNDS: u8 = 4;
+
+/// The key of the permutation that scatters hidden bits over the image.
+#[derive(Clone)]
+pub struct ScatterKey([u8; 32]);
+
+impl ScatterKey {
+    /// Derives the key from a password with Argon2id.
+    pub fn derive(password: &str) -> Result<Self> {
+        Self::derive_with(password, Argon2Params::default())
+    }
+
+    fn derive_with(password: &str, params: Argon2Params) -> Result<Self> {
+        let kdf = Kdf::Argon2id { params, salt: SCATTER_SALT.to_vec() };
+        Ok(ScatterKey(envelope::derive_key(&kdf, password)?))
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        ScatterKey(bytes)
+    }
+}
+
+impl fmt::Debug for ScatterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ScatterKey(..)")
+    }
+}
+
+/// A keyed permutation of `0..len`: a balanced Feistel network over the smallest power
+/// of four not below `len`, re-applied until the result is in range (cycle walking).
+/// Positions are computed one at a time, so the permutation never has to be stored.
+struct Permutation {
+    key: [u8; 32],
+    len: u64,
+    half_bits: u32,
+}
+
+impl Permutation {
+    fn new(key: &ScatterKey, len: u64) -> Self {
+        let bits = 64 - len.saturating_sub(1).leading_zeros();
+        Permutation { key: key.0, len, half_bits: bits.div_ceil(2).max(1) }
+    }
+
+    fn apply(&self, index: u64) -> u64 {
+        let mut value = index;
+        loop {
+            value = self.feistel(value);
+            if value < self.len {
+                return value;
+            }
+        }
+    }
+
+    fn feistel(&self, value: u64) -> u64 {
+        let mask = (1 << self.half_bits) - 1;
+        let (mut left, mut right) = (value >> self.half_bits, value & mask);
+        for round in 0..FEISTEL_ROUNDS {
+            let mut hasher = blake3::Hasher::new_keyed(&self.key);
+            hasher.update(&[round]);
+            hasher.update(&right.to_be_bytes());
+            let output = u64::from_be_bytes(hasher.finalize().as_bytes()[..8].try_into().unwrap());
+            (left, right) = (right, left ^ (output & mask));
+        }
+        (left << self.half_bits) | right
+    }
+}
+
+/// The samples of each pixel that carry hidden bits. For grayscale images any color
+/// channel selects the gray sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channels {
+    pub red: bool,
+    pub green: bool,
+    pub blue: bool,
+    pub alpha: bool,
+}
+
+impl Default for Channels {
+    /// The color channels: changes to alpha are easier to spot in flat transparent areas.
+    fn default() -> Self {
+        Channels {
+            red: true,
+            green: true,
+            blue: true,
+            alpha: false,
+        }
+    }
+}
+
+impl FromStr for Channels {
+    type Err = Error;
+
+    /// Parses a set of channel letters such as `rgb` or `ba`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut channels = Channels {
+            red: false,
+            green: false,
+            blue: false,
+            alpha: false,
+        };
+        let invalid = || Error::InvalidChannels { channels: s.to_string() };
+        for letter in s.chars() {
+            let channel = match letter {
+                'r' => &mut channels.red,
+                'g' => &mut channels.green,
+                'b' => &mut channels.blue,
+                'a' => &mut channels.alpha,
+                _ => return Err(invalid()),
+            };
+            *channel = true;
+        }
+        if s.is_empty() {
+            return Err(invalid());
+        }
+        Ok(channels)
+    }
+}
+
+impl fmt::Display for Channels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (selected, letter) in [
+            (self.red, 'r'),
+            (self.green, 'g'),
+            (self.blue, 'b'),
+            (self.alpha, 'a'),
+        ] {
+            if selected {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Maps the n-th hidden bit to the byte of the pixel buffer holding it.
+struct Layout {
+    /// Offsets of the selected samples' low bytes within a pixel.
+    offsets: Vec<usize>,
+    pixel_bytes: usize,
+    pixels: usize,
+    permutation: Option<Permutation>,
+}
+
+impl Layout {
+    fn new(ihdr: &Ihdr, channels: Channels, key: Option<&ScatterKey>) -> Result<Self> {
+        let unsupported = |reason: String| Err(Error::UnsupportedImage { reason });
+        if ihdr.color_type == ColorType::Indexed {
+            return unsupported("indexed images can not hold hidden bits".to_string());
+        }
+        if ihdr.bit_depth < 8 {
+            return unsupported(format!("{}-bit samples can not hold hidden bits", ihdr.bit_depth));
+        }
+
+        let sample_bytes = ihdr.bit_depth as usize / 8;
+        let offsets: Vec<usize> = selected_samples(ihdr.color_type, channels)
+            .into_iter()
+            .map(|sample| sample * sample_bytes + sample_bytes - 1)
+            .collect();
+        if offsets.is_empty() {
+            return unsupported(format!("{} images have none of the channels {}", ihdr.color_type, channels));
+        }
+        let pixels = (ihdr.width as usize).checked_mul(ihdr.height as usize);
+        let Some(pixels) = pixels.filter(|pixels| pixels.checked_mul(offsets.len()).is_some()) else {
+            return unsupported(format!("{}x{} is too many pixels", ihdr.width, ihdr.height));
+        };
+        let bits = (pixels * offsets.len()) as u64;
+        Ok(Layout {
+            offsets,
+            pixel_bytes: ihdr.filter_stride(),
+            pixels,
+            permutation: key.map(|key| Permutation::new(key, bits)),
+        })
+    }
+
+    fn bits(&self) -> usize {
+        self.pixels * self.offsets.len()
+    }
+
+    fn position(&self, bit: usize) -> usize {
+        let bit = match &self.permutation {
+            Some(permutation) => permutation.apply(bit as u64) as usize,
+            None => bit,
+        };
+        let pixel = bit / self.offsets.len();
+        pixel * self.pixel_bytes + self.offsets[bit % self.offsets.len()]
+    }
+}
+
+/// The indices within a pixel of the samples `channels` selects.
+fn selected_samples(color_type: ColorType, channels: Channels) -> Vec<usize> {
+    let color = channels.red || channels.green || channels.blue;
+    let selected: &[bool] = match color_type {
+        ColorType::Indexed => &[],
+        ColorType::Grayscale => &[color],
+        ColorType::GrayscaleAlpha => &[color, channels.alpha],
+        ColorType::Rgb => &[channels.red, channels.green, channels.blue],
+        ColorType::Rgba => &[channels.red, channels.green, channels.blue, channels.alpha],
+    };
+    (0..selected.len()).filter(|&sample| selected[sample]).collect()
+}
+
+/// How many bits of each pixel could carry data when the `bits` low bits of every
+/// selected sample are used, or `None` if that would overwrite whole samples. Palette
+/// indices never carry data: their low bits pick unrelated colors.
+pub fn bits_per_pixel(ihdr: &Ihdr, channels: Channels, bits: u8) -> Option<usize> {
+    if ihdr.color_type == ColorType::Indexed || bits == 0 || bits >= ihdr.bit_depth {
+        return None;
+    }
+    Some(selected_samples(ihdr.color_type, channels).len() * bits as usize)
+}
+
+/// How many bytes of data fit in an image at `bits` bits per selected sample, after the
+/// header. Only 1 bit per sample is used by [`embed`]; this gives an upper bound for
+/// the others.
+pub fn theoretical_capacity(ihdr: &Ihdr, channels: Channels, bits: u8) -> Option<u64> {
+    let bits_per_pixel = bits_per_pixel(ihdr, channels, bits)? as u64;
+    let bits = u128::from(ihdr.width) * u128::from(ihdr.height) * u128::from(bits_per_pixel);
+    let bytes = u64::try_from(bits / 8).ok()?;
+    Some(bytes.saturating_sub(HEADER_LENGTH as u64))
+}
+
+/// How many bytes of data fit in the selected channels of an image.
+pub fn capacity(ihdr: &Ihdr, channels: Channels) -> Result<usize> {
+    let bytes = Layout::new(ihdr, channels, None)?.bits() / 8;
+    Ok(bytes.saturating_sub(HEADER_LENGTH))
+}
+
+/// Hides `data`, labelled with `chunk_type`, in the pixels of `png`, scattered by `key`
+/// if there is one.
+pub fn embed(
+    png: &mut Png,
+    chunk_type: &ChunkType,
+    data: &[u8],
+    channels: Channels,
+    key: Option<&ScatterKey>,
+) -> Result<()> {
+    let ihdr = png.header_info()?;
+    let layout = Layout::new(&ihdr, channels, key)?;
+    let available = capacity(&ihdr, channels)?;
+    if data.len() > available || data.len() > u32::MAX as usize {
+        return Err(Error::CapacityExceeded { needed: data.len(), available });
+    }
+
+    let mut scanlines = Scanlines::read(png)?;
+    let pixels = scanlines.pixels_mut();
+    let header = MAGIC
+        .iter()
+        .chain(&chunk_type.bytes())
+        .chain(&(data.len() as u32).to_be_bytes())
+        .copied()
+        .collect::<Vec<u8>>();
+    for (i, byte) in header.iter().chain(data).enumerate() {
+        for bit in 0..8 {
+            let position = layout.position(i * 8 + bit);
+            pixels[position] = (pixels[position] & !1) | ((byte >> (7 - bit)) & 1);
+        }
+    }
+    scanlines.write_to(png)
+}
+
+/// Recovers the data hidden in `png` by [`embed`] with the same `chunk_type`,
+/// `channels` and `key`.
+pub fn extract(
+    png: &Png,
+    chunk_type: &ChunkType,
+    channels: Channels,
+    key: Option<&ScatterKey>,
+) -> Result<Vec<u8>> {
+    let ihdr = png.header_info()?;
+    let layout = Layout::new(&ihdr, channels, key)?;
+    let scanlines = Scanlines::read(png)?;
+    let pixels = scanlines.pixels();
+    let read_byte = |i: usize| {
+        (0..8).fold(0u8, |byte, bit| (byte << 1) | (pixels[layout.position(i * 8 + bit)] & 1))
+    };
+
+    let not_found = |reason: &str| Error::MessageNotFound { reason: reason.to_string() };
+    if layout.bits() < HEADER_LENGTH * 8 {
+        return Err(not_found("the image is too small to hold a message"));
+    }
+    let header: Vec<u8> = (0..HEADER_LENGTH).map(read_byte).collect();
+    if header[..4] != MAGIC {
+        return Err(not_found("no hidden data in the selected channels"));
+    }
+    if header[4..8] != chunk_type.bytes() {
+        return Err(not_found(&format!(
+            "the hidden message is labelled {}, not {}",
+            String::from_utf8_lossy(&header[4..8]),
+            chunk_type
+        )));
+    }
+    let length = u32::from_be_bytes(header[8..].try_into().unwrap()) as usize;
+    if length > capacity(&ihdr, channels)? {
+        return Err(not_found("the hidden length is larger than the image"));
+    }
+    Ok((HEADER_LENGTH..HEADER_LENGTH + length).map(read_byte).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::idat::tests::testing_image;
+
+    fn chunk_type() -> ChunkType {
+        ChunkType::from_str("RuSt").unwrap()
+    }
+
+    #[test]
+    fn test_embed_then_extract() {
+        let mut png = testing_image(20, 10);
+        let before = Scanlines::read(&png).unwrap().pixels().to_vec();
+        embed(&mut png, &chunk_type(), b"hidden in plain sight", Channels::default(), None).unwrap();
+
+        let after = Scanlines::read(&png).unwrap().pixels().to_vec();
+        assert!(before.iter().zip(&after).all(|(a, b)| a >> 1 == b >> 1));
+        assert_ne!(before, after);
+        let data = extract(&png, &chunk_type(), Channels::default(), None).unwrap();
+        assert_eq!(data, b"hidden in plain sight");
+    }
+
+    #[test]
+    fn test_survives_chunk_round_trip() {
+        let mut png = testing_image(16, 16);
+        embed(&mut png, &chunk_type(), b"bytes", Channels::from_str("gb").unwrap(), None).unwrap();
+        let reread = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert_eq!(extract(&reread, &chunk_type(), Channels::from_str("gb").unwrap(), None).unwrap(), b"bytes");
+        assert!(extract(&reread, &chunk_type(), Channels::default(), None).is_err());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let png = testing_image(20, 10);
+        let ihdr = png.header_info().unwrap();
+        assert_eq!(capacity(&ihdr, Channels::default()).unwrap(), 20 * 10 * 3 / 8 - 12);
+        assert!(capacity(&ihdr, Channels::from_str("a").unwrap()).is_err());
+
+        let mut png = testing_image(4, 4);
+        let result = embed(&mut png, &chunk_type(), &[0; 10], Channels::default(), None);
+        assert!(matches!(result, Err(Error::CapacityExceeded { needed: 10, available: 0 })));
+    }
+
+    #[test]
+    fn test_theoretical_capacity() {
+        let png = testing_image(20, 10);
+        let mut ihdr = png.header_info().unwrap();
+        for bits in 1..=MAX_BITS_PER_SAMPLE {
+            assert_eq!(bits_per_pixel(&ihdr, Channels::default(), bits), Some(3 * bits as usize));
+        }
+        assert_eq!(
+            theoretical_capacity(&ihdr, Channels::default(), 1),
+            Some(capacity(&ihdr, Channels::default()).unwrap() as u64)
+        );
+        assert_eq!(theoretical_capacity(&ihdr, Channels::default(), 4), Some(20 * 10 * 12 / 8 - 12));
+
+        ihdr.color_type = ColorType::Grayscale;
+        ihdr.bit_depth = 2;
+        assert_eq!(bits_per_pixel(&ihdr, Channels::default(), 1), Some(1));
+        assert_eq!(bits_per_pixel(&ihdr, Channels::default(), 2), None);
+        ihdr.color_type = ColorType::Indexed;
+        assert_eq!(bits_per_pixel(&ihdr, Channels::default(), 1), None);
+    }
+
+    #[test]
+    fn test_no_hidden_message() {
+        let png = testing_image(20, 10);
+        assert!(matches!(
+            extract(&png, &chunk_type(), Channels::default(), None),
+            Err(Error::MessageNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_wrong_label() {
+        let mut png = testing_image(20, 10);
+        embed(&mut png, &chunk_type(), b"hi", Channels::default(), None).unwrap();
+        let other = ChunkType::from_str("OtHr").unwrap();
+        assert!(matches!(extract(&png, &other, Channels::default(), None), Err(Error::MessageNotFound { .. })));
+    }
+
+    #[test]
+    fn test_permutation_is_a_bijection() {
+        let key = ScatterKey::from_bytes([7; 32]);
+        for len in [1, 2, 3, 17, 64, 1000] {
+            let permutation = Permutation::new(&key, len);
+            let mut seen: Vec<u64> = (0..len).map(|i| permutation.apply(i)).collect();
+            seen.sort();
+            assert_eq!(seen, (0..len).collect::<Vec<u64>>());
+        }
+        let other = Permutation::new(&ScatterKey::from_bytes([8; 32]), 1000);
+        let permutation = Permutation::new(&key, 1000);
+        assert!((0..1000).any(|i| permutation.apply(i) != other.apply(i)));
+    }
+
+    #[test]
+    fn test_scattered_embedding() {
+        let key = ScatterKey::from_bytes([1; 32]);
+        let mut png = testing_image(40, 40);
+        let before = Scanlines::read(&png).unwrap().pixels().to_vec();
+        embed(&mut png, &chunk_type(), b"spread out", Channels::default(), Some(&key)).unwrap();
+        assert_eq!(extract(&png, &chunk_type(), Channels::default(), Some(&key)).unwrap(), b"spread out");
+
+        // The changed bytes are not all at the top of the image.
+        let after = Scanlines::read(&png).unwrap().pixels().to_vec();
+        let last_change = (0..after.len()).rev().find(|&i| before[i] != after[i]).unwrap();
+        assert!(last_change > after.len() / 2);
+
+        assert!(extract(&png, &chunk_type(), Channels::default(), None).is_err());
+        let wrong = ScatterKey::from_bytes([2; 32]);
+        assert!(extract(&png, &chunk_type(), Channels::default(), Some(&wrong)).is_err());
+    }
+
+    #[test]
+    fn test_derived_keys() {
+        let cheap = Argon2Params { m_cost: 64, t_cost: 1, p_cost: 1 };
+        let key = ScatterKey::derive_with("password", cheap).unwrap();
+        assert_eq!(key.0, ScatterKey::derive_with("password", cheap).unwrap().0);
+        assert_ne!(key.0, ScatterKey::derive_with("passw0rd", cheap).unwrap().0);
+    }
+
+    #[test]
+    fn test_parse_channels() {
+        assert_eq!(Channels::from_str("rgb").unwrap(), Channels::default());
+        assert_eq!(Channels::from_str("ar").unwrap().to_string(), "ra");
+        assert!(Channels::from_str("x").is_err());
+        assert!(Channels::from_str("").is_err());
+    }
+}
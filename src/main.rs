@@ -1,18 +1,69 @@
 mod args;
-mod chunk;
-mod chunk_type;
 mod commands;
-mod png;
+mod output;
+use std::process::ExitCode;
+
 use clap::Parser;
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+use pngme::ihdr::Interlace;
+use pngme::Error;
 
-fn main() -> Result<()> {
-    match args::PngMeArgs::parse() {
+fn main() -> ExitCode {
+    let result = match args::PngMeArgs::parse() {
         args::PngMeArgs::Encode(encode_args) => commands::encode(encode_args),
         args::PngMeArgs::Decode(decode_args) => commands::decode(decode_args),
         args::PngMeArgs::Remove(remove_args) => commands::remove(remove_args),
         args::PngMeArgs::Print(print_args) => commands::print(print_args),
+        args::PngMeArgs::Verify(verify_args) => commands::verify(verify_args),
+        args::PngMeArgs::Seal(seal_args) => commands::seal(seal_args),
+        args::PngMeArgs::CheckSeal(check_seal_args) => commands::check_seal(check_seal_args),
+        args::PngMeArgs::Keygen(keygen_args) => commands::keygen(keygen_args),
+        args::PngMeArgs::Capacity(capacity_args) => commands::capacity(capacity_args),
+        args::PngMeArgs::Interlace(interlace_args) => {
+            commands::interlace(interlace_args, Interlace::Adam7)
+        }
+        args::PngMeArgs::Deinterlace(interlace_args) => {
+            commands::interlace(interlace_args, Interlace::None)
+        }
+        args::PngMeArgs::Recompress(recompress_args) => commands::recompress(recompress_args),
+        args::PngMeArgs::ExportPixels(export_args) => commands::export_pixels(export_args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+// Exit code 2 is left to clap for usage errors.
+fn exit_code(error: &Error) -> u8 {
+    match error {
+        Error::ChunkNotFound { .. } => 3,
+        Error::InvalidChunkType { .. } => 4,
+        Error::BadSignature => 5,
+        Error::TruncatedChunk { .. } => 6,
+        Error::CrcMismatch { .. } => 7,
+        Error::ChunkTooLong { .. } => 8,
+        Error::InvalidUtf8(_) => 9,
+        Error::Io(_) => 10,
+        Error::IndexOutOfRange { .. } => 11,
+        Error::InvalidPosition { .. } => 12,
+        Error::InvalidHeader { .. } => 13,
+        Error::InvalidEnvelope { .. } => 14,
+        Error::KeyRequired { .. } => 15,
+        Error::DecryptionFailed => 16,
+        Error::InvalidKey { .. } => 17,
+        Error::InvalidSignature { .. } => 18,
+        Error::InvalidSeal { .. } => 19,
+        Error::InvalidParts { .. } => 20,
+        Error::Decompression { .. } => 21,
+        Error::InvalidImageData { .. } => 22,
+        Error::UnsupportedImage { .. } => 23,
+        Error::InvalidChannels { .. } => 24,
+        Error::CapacityExceeded { .. } => 25,
+        Error::MessageNotFound { .. } => 26,
+        Error::InvalidPixelFormat { .. } => 27,
     }
 }
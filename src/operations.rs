@@ -0,0 +1,446 @@
+//! Operations on an in-memory [`Png`] backing the `pngme` subcommands.
+//!
+//! These never touch the filesystem or print anything, so they can be reused
+//! by other programs; reading and writing files is left to the caller.
+
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::envelope::{self, Verification};
+use crate::keys::VerifyingKey;
+use crate::parts;
+use crate::png::{Png, Position};
+use crate::reader::{ChunkReader, StreamedChunk};
+use crate::seal;
+use crate::writer::ChunkWriter;
+use crate::{Error, Result};
+
+/// Which of the chunks sharing a type an operation applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    First,
+    Last,
+    /// The n-th chunk of that type, counting from 0.
+    Nth(usize),
+    All,
+}
+
+impl Selection {
+    /// Picks the selected items, consuming no more of `items` than needed.
+    pub fn pick<T, I: Iterator<Item = T>>(self, mut items: I) -> Vec<T> {
+        match self {
+            Selection::First => items.next().into_iter().collect(),
+            Selection::Last => items.last().into_iter().collect(),
+            Selection::Nth(n) => items.nth(n).into_iter().collect(),
+            Selection::All => items.collect(),
+        }
+    }
+}
+
+/// Returns the indices in [`Png::chunks`] of the selected chunks of type `chunk_type`.
+///
+/// Fails with [`Error::ChunkNotFound`] when the selection is empty.
+pub fn select(png: &Png, chunk_type: &str, selection: Selection) -> Result<Vec<usize>> {
+    let wanted = ChunkType::from_str(chunk_type)?;
+    let matching = png
+        .chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| *c.chunk_type() == wanted)
+        .map(|(index, _)| index);
+    let selected = selection.pick(matching);
+    if selected.is_empty() {
+        return Err(Error::ChunkNotFound { chunk_type: chunk_type.to_string() });
+    }
+    Ok(selected)
+}
+
+/// Like [`select`], but reads the chunks from a stream and only keeps the selected ones
+/// in memory. The data of every other chunk is skipped.
+pub fn select_from_reader<R: Read>(
+    reader: R,
+    chunk_type: &str,
+    selection: Selection,
+) -> Result<Vec<StreamedChunk>> {
+    let wanted = ChunkType::from_str(chunk_type)?;
+    let skipped = wanted.clone();
+    let chunks = ChunkReader::new(reader)?.skip_data(move |t| *t != skipped);
+
+    let mut error = None;
+    let matching = chunks
+        .map_while(|chunk| chunk.map_err(|e| error = Some(e)).ok())
+        .filter(|chunk| *chunk.chunk_type() == wanted);
+    let selected = selection.pick(matching);
+    if let Some(e) = error {
+        return Err(e);
+    }
+    if selected.is_empty() {
+        return Err(Error::ChunkNotFound { chunk_type: chunk_type.to_string() });
+    }
+    Ok(selected)
+}
+
+/// Reads the stored data of the selected messages in chunks of type `chunk_type`.
+///
+/// Messages split across several chunks are reassembled first, so the selection counts
+/// messages rather than chunks. Each message comes with its first chunk.
+pub fn messages_from_reader<R: Read>(
+    reader: R,
+    chunk_type: &str,
+    selection: Selection,
+) -> Result<Vec<(StreamedChunk, Vec<u8>)>> {
+    let chunks = select_from_reader(reader, chunk_type, Selection::All)?
+        .into_iter()
+        .map(|streamed| {
+            let data = streamed.chunk().map(|chunk| chunk.data().to_vec()).unwrap_or_default();
+            (streamed, data)
+        })
+        .collect();
+    let selected = selection.pick(parts::reassemble(chunks)?.into_iter());
+    if selected.is_empty() {
+        return Err(Error::ChunkNotFound { chunk_type: chunk_type.to_string() });
+    }
+    Ok(selected)
+}
+
+/// Stores `message` in a new chunk of type `chunk_type` inserted at `position`.
+pub fn encode(png: &mut Png, chunk_type: &str, message: &str, position: Position) -> Result<()> {
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    png.insert_chunk(position, Chunk::new(chunk_type, message.as_bytes().to_vec()))
+}
+
+/// Copies the PNG in `reader` to `writer` one chunk at a time, inserting `message` in a
+/// new chunk of type `chunk_type` at `position` on the way.
+///
+/// This gives the same result as [`encode`] without ever holding more than one chunk in
+/// memory. Since `writer` is written to as chunks are read, a failure can leave a partial
+/// PNG behind.
+pub fn encode_stream<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    chunk_type: &str,
+    message: &str,
+    position: Position,
+) -> Result<W> {
+    let chunk = Chunk::new(ChunkType::from_str(chunk_type)?, message.as_bytes().to_vec());
+    insert_stream(reader, writer, vec![chunk], position)
+}
+
+/// Copies the PNG in `reader` to `writer` one chunk at a time, inserting `chunks` in
+/// order at `position` on the way. See [`encode_stream`].
+pub fn insert_stream<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    chunks: Vec<Chunk>,
+    position: Position,
+) -> Result<W> {
+    let mut pending = Some(chunks);
+    let mut output = ChunkWriter::new(writer)?;
+    let mut count = 0;
+    let mut seen_iend = false;
+    for (index, chunk) in ChunkReader::new(reader)?.full_chunks().enumerate() {
+        let chunk = chunk?;
+        let chunk_type = chunk.chunk_type().bytes();
+        let insert_before = match position {
+            Position::BeforeIend => &chunk_type == b"IEND",
+            Position::BeforeFirstIdat => &chunk_type == b"IDAT",
+            Position::Index(i) => i == index && i >= 1 && !seen_iend,
+            Position::AfterIhdr => false,
+        };
+        if insert_before {
+            for new_chunk in pending.take().unwrap_or_default() {
+                output.write_chunk(&new_chunk)?;
+            }
+        }
+        output.write_chunk(&chunk)?;
+        if position == Position::AfterIhdr && &chunk_type == b"IHDR" {
+            for new_chunk in pending.take().unwrap_or_default() {
+                output.write_chunk(&new_chunk)?;
+            }
+        }
+        seen_iend |= &chunk_type == b"IEND";
+        count = index + 1;
+    }
+
+    if let Some(new_chunks) = pending {
+        match position {
+            Position::BeforeIend => {}
+            Position::Index(index) if index == count && index >= 1 && !seen_iend => {}
+            Position::Index(index) => return Err(Error::IndexOutOfRange { index, len: count }),
+            Position::AfterIhdr => return Err(Error::ChunkNotFound { chunk_type: "IHDR".to_string() }),
+            Position::BeforeFirstIdat => {
+                return Err(Error::ChunkNotFound { chunk_type: "IDAT".to_string() })
+            }
+        }
+        for new_chunk in new_chunks {
+            output.write_chunk(&new_chunk)?;
+        }
+    }
+    output.finish()
+}
+
+/// Returns the SHA-256 digest of the concatenated data of every `IDAT` chunk in `reader`.
+pub fn idat_digest<R: Read>(reader: R) -> Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    let chunks = ChunkReader::new(reader)?.skip_data(|t| &t.bytes() != b"IDAT");
+    for chunk in chunks.full_chunks() {
+        hasher.update(chunk?.data());
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// Checks the signature of every custom chunk in `reader`, that is every chunk whose type
+/// is not [standard](ChunkType::is_standard), against the trusted `key`. The
+/// [seal](crate::seal) chunk is left to [`seal::check`](crate::seal::check).
+///
+/// `idat_digest` is the [`idat_digest`] of the same image.
+pub fn verify_stream<R: Read>(
+    reader: R,
+    key: &VerifyingKey,
+    idat_digest: &[u8; 32],
+) -> Result<Vec<(StreamedChunk, Verification)>> {
+    let chunks = ChunkReader::new(reader)?.skip_data(|chunk_type| {
+        chunk_type.is_standard() || chunk_type.to_string() == seal::SEAL_CHUNK_TYPE
+    });
+    let mut verified = Vec::new();
+    for chunk in chunks {
+        let chunk = chunk?;
+        if let Some(full) = chunk.chunk() {
+            let verification = envelope::verify(full.data(), full.chunk_type(), key, idat_digest);
+            verified.push((chunk, verification));
+        }
+    }
+    Ok(verified)
+}
+
+/// Returns the message stored in the first chunk of type `chunk_type`.
+pub fn decode(png: &Png, chunk_type: &str) -> Result<String> {
+    let chunk = png
+        .chunk_by_type(chunk_type)
+        .ok_or_else(|| Error::ChunkNotFound { chunk_type: chunk_type.to_string() })?;
+    chunk.data_as_string()
+}
+
+/// Returns the messages stored in the selected chunks of type `chunk_type`.
+pub fn decode_selected(png: &Png, chunk_type: &str, selection: Selection) -> Result<Vec<String>> {
+    select(png, chunk_type, selection)?
+        .into_iter()
+        .map(|index| png.chunks()[index].data_as_string())
+        .collect()
+}
+
+/// Removes the first chunk of type `chunk_type` and returns it.
+pub fn remove(png: &mut Png, chunk_type: &str) -> Result<Chunk> {
+    png.remove_chunk(chunk_type)
+}
+
+/// Removes the selected chunks of type `chunk_type` and returns them.
+pub fn remove_selected(png: &mut Png, chunk_type: &str, selection: Selection) -> Result<Vec<Chunk>> {
+    let selected = select(png, chunk_type, selection)?;
+    let mut index = 0;
+    Ok(png.retain_chunks(|_| {
+        let keep = !selected.contains(&index);
+        index += 1;
+        keep
+    }))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        let chunk_type = ChunkType::from_str("FrSt").unwrap();
+        Png::from_chunks(vec![Chunk::new(chunk_type, b"I am the first chunk".to_vec())])
+    }
+
+    #[test]
+    fn test_encode_then_decode() {
+        let mut png = testing_png();
+        encode(&mut png, "RuSt", "hidden message", Position::BeforeIend).unwrap();
+        assert_eq!(decode(&png, "RuSt").unwrap(), "hidden message");
+    }
+
+    /// An image made of IHDR, two IDAT and IEND chunks, each holding its own type as data.
+    pub(crate) fn testing_image() -> Png {
+        let chunks = ["IHDR", "IDAT", "IDAT", "IEND"]
+            .iter()
+            .map(|t| Chunk::new(ChunkType::from_str(t).unwrap(), t.as_bytes().to_vec()))
+            .collect();
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_encode_stream_matches_encode() {
+        let positions = [
+            Position::BeforeIend,
+            Position::AfterIhdr,
+            Position::BeforeFirstIdat,
+            Position::Index(1),
+            Position::Index(3),
+        ];
+        for position in positions {
+            let mut png = testing_image();
+            let bytes = png.as_bytes();
+            encode(&mut png, "RuSt", "hidden message", position).unwrap();
+
+            let streamed =
+                encode_stream(bytes.as_slice(), Vec::new(), "RuSt", "hidden message", position)
+                    .unwrap();
+            assert_eq!(streamed, png.as_bytes(), "{:?}", position);
+        }
+    }
+
+    #[test]
+    fn test_encode_stream_errors() {
+        let bytes = testing_png().as_bytes();
+        let result = encode_stream(bytes.as_slice(), Vec::new(), "RuSt", "hi", Position::AfterIhdr);
+        assert!(matches!(result, Err(Error::ChunkNotFound { .. })));
+        let result = encode_stream(bytes.as_slice(), Vec::new(), "RuSt", "hi", Position::Index(5));
+        assert!(matches!(result, Err(Error::IndexOutOfRange { index: 5, len: 1 })));
+
+        // Nothing goes before the first chunk or after IEND.
+        let bytes = testing_image().as_bytes();
+        for index in [0, 4] {
+            let result = encode_stream(bytes.as_slice(), Vec::new(), "RuSt", "hi", Position::Index(index));
+            assert!(matches!(result, Err(Error::IndexOutOfRange { len: 4, .. })), "{}", index);
+        }
+    }
+
+    #[test]
+    fn test_verify_stream() {
+        let key = crate::keys::SigningKey::generate();
+        let mut png = testing_image();
+        let digest = idat_digest(png.as_bytes().as_slice()).unwrap();
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let signed = envelope::sign(b"signed", &chunk_type, &key, Some(&digest)).unwrap();
+        png.insert_chunk(Position::BeforeIend, Chunk::new(chunk_type.clone(), signed)).unwrap();
+        png.insert_chunk(Position::BeforeIend, Chunk::new(chunk_type, b"plain".to_vec())).unwrap();
+        seal::seal(&mut png, seal::SealHash::default(), &key).unwrap();
+
+        let bytes = png.as_bytes();
+        let verified = verify_stream(bytes.as_slice(), &key.verifying_key(), &digest).unwrap();
+        let results: Vec<_> = verified.iter().map(|(c, v)| (c.index(), *v)).collect();
+        assert_eq!(results, [(3, Verification::Valid), (4, Verification::Unsigned)]);
+    }
+
+    #[test]
+    fn test_verify_stream_checks_signed_parts() {
+        let key = crate::keys::SigningKey::generate();
+        let mut png = testing_image();
+        let digest = idat_digest(png.as_bytes().as_slice()).unwrap();
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        for part in parts::split(b"signed in two parts", 2).unwrap() {
+            let signed = envelope::sign(&part, &chunk_type, &key, None).unwrap();
+            png.insert_chunk(Position::BeforeIend, Chunk::new(chunk_type.clone(), signed)).unwrap();
+        }
+
+        let bytes = png.as_bytes();
+        let verified = verify_stream(bytes.as_slice(), &key.verifying_key(), &digest).unwrap();
+        let results: Vec<_> = verified.iter().map(|(_, v)| *v).collect();
+        assert_eq!(results, [Verification::Valid, Verification::Valid]);
+        let messages = messages_from_reader(bytes.as_slice(), "RuSt", Selection::All).unwrap();
+        assert_eq!(messages[0].1, b"signed in two parts");
+    }
+
+    #[test]
+    fn test_decode_missing_chunk() {
+        let png = testing_png();
+        assert!(decode(&png, "RuSt").is_err());
+    }
+
+    #[test]
+    fn test_encode_invalid_chunk_type() {
+        let mut png = testing_png();
+        assert!(encode(&mut png, "Rust!", "hidden message", Position::BeforeIend).is_err());
+    }
+
+    #[test]
+    fn test_decode_selected() {
+        let mut png = testing_png();
+        for message in ["one", "two", "three"] {
+            encode(&mut png, "RuSt", message, Position::BeforeIend).unwrap();
+        }
+        assert_eq!(decode_selected(&png, "RuSt", Selection::First).unwrap(), ["one"]);
+        assert_eq!(decode_selected(&png, "RuSt", Selection::Last).unwrap(), ["three"]);
+        assert_eq!(decode_selected(&png, "RuSt", Selection::Nth(1)).unwrap(), ["two"]);
+        assert_eq!(
+            decode_selected(&png, "RuSt", Selection::All).unwrap(),
+            ["one", "two", "three"]
+        );
+        assert!(decode_selected(&png, "RuSt", Selection::Nth(3)).is_err());
+    }
+
+    #[test]
+    fn test_select_indices() {
+        let mut png = testing_png();
+        encode(&mut png, "RuSt", "one", Position::BeforeIend).unwrap();
+        encode(&mut png, "RuSt", "two", Position::BeforeIend).unwrap();
+        assert_eq!(select(&png, "RuSt", Selection::All).unwrap(), [1, 2]);
+        assert!(matches!(
+            select(&png, "NoNe", Selection::First),
+            Err(Error::ChunkNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_select_from_reader() {
+        let mut png = testing_png();
+        for message in ["one", "two", "three"] {
+            encode(&mut png, "RuSt", message, Position::BeforeIend).unwrap();
+        }
+        let bytes = png.as_bytes();
+        let selected = select_from_reader(bytes.as_slice(), "RuSt", Selection::Last).unwrap();
+        assert_eq!(selected[0].index(), 3);
+        assert_eq!(selected[0].chunk().unwrap().data(), b"three");
+        let selected = select_from_reader(bytes.as_slice(), "RuSt", Selection::All).unwrap();
+        assert_eq!(selected.len(), 3);
+        assert!(select_from_reader(bytes.as_slice(), "NoNe", Selection::All).is_err());
+    }
+
+    #[test]
+    fn test_messages_from_reader_reassembles_parts() {
+        let mut png = testing_image();
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        encode(&mut png, "RuSt", "whole", Position::BeforeIend).unwrap();
+        for part in parts::split(b"split in three", 3).unwrap().into_iter().rev() {
+            png.insert_chunk(Position::AfterIhdr, Chunk::new(chunk_type.clone(), part)).unwrap();
+        }
+        let bytes = png.as_bytes();
+
+        let messages = messages_from_reader(bytes.as_slice(), "RuSt", Selection::All).unwrap();
+        let found: Vec<_> = messages.iter().map(|(c, data)| (c.index(), data.as_slice())).collect();
+        assert_eq!(found, [(1, &b"split in three"[..]), (6, b"whole")]);
+        let last = messages_from_reader(bytes.as_slice(), "RuSt", Selection::Last).unwrap();
+        assert_eq!(last[0].1, b"whole");
+    }
+
+    #[test]
+    fn test_remove_selected() {
+        let mut png = testing_png();
+        for message in ["one", "two", "three"] {
+            encode(&mut png, "RuSt", message, Position::BeforeIend).unwrap();
+        }
+        let removed = remove_selected(&mut png, "RuSt", Selection::Nth(1)).unwrap();
+        assert_eq!(removed[0].data(), b"two");
+        assert_eq!(decode_selected(&png, "RuSt", Selection::All).unwrap(), ["one", "three"]);
+
+        let removed = remove_selected(&mut png, "RuSt", Selection::All).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(png.chunks().len(), 1);
+        assert!(remove_selected(&mut png, "RuSt", Selection::All).is_err());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut png = testing_png();
+        encode(&mut png, "RuSt", "hidden message", Position::BeforeIend).unwrap();
+        let removed = remove(&mut png, "RuSt").unwrap();
+        assert_eq!(removed.data(), b"hidden message");
+        assert!(decode(&png, "RuSt").is_err());
+    }
+}
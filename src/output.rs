@@ -0,0 +1,322 @@
+//! JSON output for `--format json`.
+//!
+//! Every subcommand prints a single JSON object on stdout:
+//!
+//! - `print`: `{ "image": Image | null, "size": number, "chunks": [Chunk] }`
+//! - `decode`: `{ "chunks": [Chunk] }`; with `--mode lsb`, `chunks` is empty and the
+//!   message is reported as `"pixels": { "type", "channels", "data", "file" }`
+//! - `remove`: `{ "output": string, "removed": [Chunk] }`
+//! - `verify`: `{ "chunks": [{ "index", "offset", "type", "status" }] }`, with `status`
+//!   one of `valid`, `invalid`, `unsigned`
+//! - `check-seal`: `{ "hash": string, "signature_valid": bool, "intact": bool,
+//!   "chunks": [{ "type", "status" }] }`, with `status` one of `unchanged`, `changed`,
+//!   `missing`, `added`
+//! - `recompress`: `{ "output": string, "before": Size, "after": Size, "delta": number }`,
+//!   with `Size` being `{ "idat_chunks", "idat_bytes", "file_bytes" }` and `delta` the
+//!   change in file size
+//! - `capacity`: `{ "image": Image, "channels": string, "levels": [{ "bits_per_channel",
+//!   "bits_per_pixel", "bytes" }], "chunk_limit": number }`, with `bits_per_pixel` and
+//!   `bytes` null where the image can not hide data at that level
+//!
+//! where
+//!
+//! - `Image` is `{ "width", "height", "bit_depth", "color_type", "interlace" }`, with
+//!   `color_type` one of `grayscale`, `RGB`, `indexed`, `grayscale+alpha`, `RGBA` and
+//!   `interlace` one of `none`, `adam7`.
+//! - `Chunk` is `{ "index", "offset", "type", "length", "crc", "critical", "public",
+//!   "reserved_bit_valid", "safe_to_copy", "data" }`. `index` and `offset` locate the chunk
+//!   in the file it was read from. `decode` adds `"file": { "name", "mime_type", "size" }`
+//!   to messages stored from a file.
+//! - `data` is `{ "encoding": "utf8" | "base64", "value": string }`; UTF-8 is used whenever
+//!   the chunk data is valid UTF-8. `print` does not read pixel data, so `data` is `null`
+//!   for its `IDAT` chunks. `decode` reports the decrypted message rather than the stored
+//!   bytes.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use pngme::envelope::{Message, Metadata, Verification};
+use pngme::ihdr::{Ihdr, Interlace};
+use pngme::lsb::Channels;
+use pngme::reader::StreamedChunk;
+use pngme::seal::SealReport;
+use pngme::{Chunk, Png};
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Serialize)]
+pub struct ImageJson {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: String,
+    interlace: &'static str,
+}
+
+impl From<&Ihdr> for ImageJson {
+    fn from(ihdr: &Ihdr) -> Self {
+        ImageJson {
+            width: ihdr.width,
+            height: ihdr.height,
+            bit_depth: ihdr.bit_depth,
+            color_type: ihdr.color_type.to_string(),
+            interlace: match ihdr.interlace {
+                Interlace::None => "none",
+                Interlace::Adam7 => "adam7",
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DataJson {
+    encoding: &'static str,
+    value: String,
+}
+
+impl From<&[u8]> for DataJson {
+    fn from(data: &[u8]) -> Self {
+        match std::str::from_utf8(data) {
+            Ok(text) => DataJson {
+                encoding: "utf8",
+                value: text.to_string(),
+            },
+            Err(_) => DataJson {
+                encoding: "base64",
+                value: BASE64.encode(data),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ChunkJson {
+    index: usize,
+    offset: usize,
+    #[serde(rename = "type")]
+    chunk_type: String,
+    length: u32,
+    crc: u32,
+    critical: bool,
+    public: bool,
+    reserved_bit_valid: bool,
+    safe_to_copy: bool,
+    data: Option<DataJson>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<FileJson>,
+}
+
+#[derive(Serialize)]
+pub struct FileJson {
+    name: Option<String>,
+    mime_type: Option<String>,
+    size: u64,
+}
+
+impl From<&Metadata> for FileJson {
+    fn from(metadata: &Metadata) -> Self {
+        FileJson {
+            name: metadata.file_name.clone(),
+            mime_type: metadata.mime_type.clone(),
+            size: metadata.size,
+        }
+    }
+}
+
+impl ChunkJson {
+    pub fn new(index: usize, offset: usize, chunk: &Chunk) -> Self {
+        let chunk_type = chunk.chunk_type();
+        ChunkJson {
+            index,
+            offset,
+            chunk_type: chunk_type.to_string(),
+            length: chunk.length(),
+            crc: chunk.crc(),
+            critical: chunk_type.is_critical(),
+            public: chunk_type.is_public(),
+            reserved_bit_valid: chunk_type.is_reserved_bit_valid(),
+            safe_to_copy: chunk_type.is_safe_to_copy(),
+            data: Some(DataJson::from(chunk.data())),
+            file: None,
+        }
+    }
+}
+
+impl ChunkJson {
+    /// Reports `message` instead of the chunk's stored bytes.
+    pub fn with_message(mut self, message: &Message) -> Self {
+        self.data = Some(DataJson::from(message.data.as_slice()));
+        self.file = message.metadata.as_ref().map(FileJson::from);
+        self
+    }
+}
+
+impl From<&StreamedChunk> for ChunkJson {
+    fn from(streamed: &StreamedChunk) -> Self {
+        let chunk_type = streamed.chunk_type();
+        ChunkJson {
+            index: streamed.index(),
+            offset: streamed.offset(),
+            chunk_type: chunk_type.to_string(),
+            length: streamed.length(),
+            crc: streamed.crc(),
+            critical: chunk_type.is_critical(),
+            public: chunk_type.is_public(),
+            reserved_bit_valid: chunk_type.is_reserved_bit_valid(),
+            safe_to_copy: chunk_type.is_safe_to_copy(),
+            data: streamed.chunk().map(|chunk| DataJson::from(chunk.data())),
+            file: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PrintJson {
+    pub image: Option<ImageJson>,
+    pub size: usize,
+    pub chunks: Vec<ChunkJson>,
+}
+
+#[derive(Serialize)]
+pub struct DecodeJson {
+    pub chunks: Vec<ChunkJson>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixels: Option<PixelsJson>,
+}
+
+/// A message hidden in the pixels rather than in a chunk.
+#[derive(Serialize)]
+pub struct PixelsJson {
+    #[serde(rename = "type")]
+    chunk_type: String,
+    channels: String,
+    data: DataJson,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<FileJson>,
+}
+
+impl PixelsJson {
+    pub fn new(chunk_type: &str, channels: Channels, message: &Message) -> Self {
+        PixelsJson {
+            chunk_type: chunk_type.to_string(),
+            channels: channels.to_string(),
+            data: DataJson::from(message.data.as_slice()),
+            file: message.metadata.as_ref().map(FileJson::from),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RemoveJson {
+    pub output: String,
+    pub removed: Vec<ChunkJson>,
+}
+
+#[derive(Serialize)]
+pub struct VerifyJson {
+    pub chunks: Vec<SignatureJson>,
+}
+
+#[derive(Serialize)]
+pub struct SignatureJson {
+    index: usize,
+    offset: usize,
+    #[serde(rename = "type")]
+    chunk_type: String,
+    status: String,
+}
+
+impl From<&(StreamedChunk, Verification)> for SignatureJson {
+    fn from((streamed, verification): &(StreamedChunk, Verification)) -> Self {
+        SignatureJson {
+            index: streamed.index(),
+            offset: streamed.offset(),
+            chunk_type: streamed.chunk_type().to_string(),
+            status: verification.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CheckSealJson {
+    hash: String,
+    signature_valid: bool,
+    intact: bool,
+    chunks: Vec<DigestJson>,
+}
+
+#[derive(Serialize)]
+pub struct DigestJson {
+    #[serde(rename = "type")]
+    chunk_type: String,
+    status: String,
+}
+
+impl From<&SealReport> for CheckSealJson {
+    fn from(report: &SealReport) -> Self {
+        CheckSealJson {
+            hash: report.hash.to_string(),
+            signature_valid: report.signature_valid,
+            intact: report.is_intact(),
+            chunks: report
+                .digests
+                .iter()
+                .map(|(chunk_type, status)| DigestJson {
+                    chunk_type: chunk_type.to_string(),
+                    status: status.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RecompressJson {
+    pub output: String,
+    pub before: SizeJson,
+    pub after: SizeJson,
+    pub delta: i64,
+}
+
+#[derive(Serialize)]
+pub struct SizeJson {
+    pub idat_chunks: usize,
+    pub idat_bytes: usize,
+    pub file_bytes: usize,
+}
+
+impl From<&Png> for SizeJson {
+    fn from(png: &Png) -> Self {
+        SizeJson {
+            idat_chunks: png.chunks_by_type("IDAT").count(),
+            idat_bytes: png.chunks_by_type("IDAT").map(|chunk| chunk.data().len()).sum(),
+            file_bytes: png.as_bytes().len(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CapacityJson {
+    pub image: ImageJson,
+    pub channels: String,
+    pub levels: Vec<CapacityLevelJson>,
+    pub chunk_limit: u32,
+}
+
+#[derive(Serialize)]
+pub struct CapacityLevelJson {
+    pub bits_per_channel: u8,
+    pub bits_per_pixel: Option<usize>,
+    pub bytes: Option<u64>,
+}
+
+pub fn print_json<T: Serialize>(value: &T) {
+    println!("{}", serde_json::to_string_pretty(value).expect("JSON output is always serializable"));
+}
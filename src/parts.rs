@@ -0,0 +1,231 @@
+//! Splitting stored data across several chunks and putting it back together.
+//!
+//! Each part is an [`Envelope`] holding a [`Part`] header and one slice of the data. The
+//! parts of one message share a random message id, so several split messages can live
+//! in chunks of the same type, in any order.
+
+use std::collections::BTreeMap;
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+
+use crate::envelope::{Envelope, Part};
+use crate::{Error, Result};
+
+/// How many missing parts an error names before it only gives their count.
+const MAX_LISTED: usize = 8;
+
+/// Splits `data` into `count` envelopes of nearly equal size, in sequence order.
+pub fn split(data: &[u8], count: u32) -> Result<Vec<Vec<u8>>> {
+    if count == 0 {
+        return Err(Error::InvalidParts {
+            message_id: 0,
+            reason: "data can not be split into 0 parts".to_string(),
+        });
+    }
+    let message_id = OsRng.next_u64();
+    let checksum = crc::crc32::checksum_ieee(data);
+    let bound = |i: u32| (data.len() as u64 * u64::from(i) / u64::from(count)) as usize;
+    Ok((0..count)
+        .map(|sequence| {
+            let part = Part {
+                message_id,
+                sequence,
+                total: count,
+                checksum,
+            };
+            Envelope {
+                part: Some(part),
+                payload: data[bound(sequence)..bound(sequence + 1)].to_vec(),
+                ..Default::default()
+            }
+            .to_bytes()
+        })
+        .collect())
+}
+
+/// Puts split data back together.
+///
+/// `items` pairs the data of each chunk with some context, such as where the chunk was
+/// found. Data that is not a part is passed through; the parts of each message are
+/// replaced by the reassembled data, paired with the context of its first part. The
+/// result keeps the order in which messages first appear.
+///
+/// Fails with [`Error::InvalidParts`] when a part is missing, duplicated or does not
+/// match the others.
+pub fn reassemble<T>(items: Vec<(T, Vec<u8>)>) -> Result<Vec<(T, Vec<u8>)>> {
+    enum Slot<T> {
+        Whole(T, Vec<u8>),
+        Split(T, u64),
+    }
+
+    let mut slots = Vec::new();
+    let mut messages: BTreeMap<u64, Vec<(Part, Vec<u8>)>> = BTreeMap::new();
+    for (context, data) in items {
+        let part = match Envelope::is_envelope(&data) {
+            true => Envelope::from_bytes(&data)?,
+            false => {
+                slots.push(Slot::Whole(context, data));
+                continue;
+            }
+        };
+        let Some(header) = part.part else {
+            slots.push(Slot::Whole(context, data));
+            continue;
+        };
+        let parts = messages.entry(header.message_id).or_default();
+        if parts.is_empty() {
+            slots.push(Slot::Split(context, header.message_id));
+        }
+        parts.push((header, part.payload));
+    }
+
+    slots
+        .into_iter()
+        .map(|slot| match slot {
+            Slot::Whole(context, data) => Ok((context, data)),
+            Slot::Split(context, message_id) => {
+                let parts = messages.remove(&message_id).unwrap_or_default();
+                Ok((context, join(message_id, parts)?))
+            }
+        })
+        .collect()
+}
+
+fn join(message_id: u64, mut parts: Vec<(Part, Vec<u8>)>) -> Result<Vec<u8>> {
+    let error = |reason: String| Error::InvalidParts { message_id, reason };
+    let (total, checksum) = (parts[0].0.total, parts[0].0.checksum);
+    if parts.iter().any(|(part, _)| part.total != total || part.checksum != checksum) {
+        return Err(error("parts disagree on the total or checksum".to_string()));
+    }
+
+    parts.sort_by_key(|(part, _)| part.sequence);
+    let sequences: Vec<u32> = parts.iter().map(|(part, _)| part.sequence).collect();
+    // The total comes from the chunk, so count the gaps instead of walking up to it.
+    let mut present = sequences.clone();
+    present.dedup();
+    let missing_count = u64::from(total) - present.iter().filter(|&&s| s < total).count() as u64;
+    let missing: Vec<u32> = (0..total)
+        .filter(|s| sequences.binary_search(s).is_err())
+        .take(MAX_LISTED)
+        .collect();
+    let mut duplicated: Vec<u32> = sequences.windows(2).filter(|w| w[0] == w[1]).map(|w| w[0]).collect();
+    duplicated.dedup();
+    let out_of_range: Vec<u32> = sequences.iter().copied().filter(|&s| s >= total).collect();
+
+    let mut problems = Vec::new();
+    if missing_count > MAX_LISTED as u64 {
+        problems.push(format!(
+            "missing {} of {} parts, starting with {}",
+            missing_count,
+            total,
+            list(&missing)
+        ));
+    } else if missing_count > 0 {
+        problems.push(format!("missing part(s) {} of {}", list(&missing), total));
+    }
+    if !duplicated.is_empty() {
+        problems.push(format!("duplicated part(s) {}", list(&duplicated)));
+    }
+    if !out_of_range.is_empty() {
+        problems.push(format!("part(s) {} beyond the total of {}", list(&out_of_range), total));
+    }
+    if !problems.is_empty() {
+        return Err(error(problems.join(", ")));
+    }
+
+    let data: Vec<u8> = parts.into_iter().flat_map(|(_, payload)| payload).collect();
+    if crc::crc32::checksum_ieee(&data) != checksum {
+        return Err(error("checksum mismatch".to_string()));
+    }
+    Ok(data)
+}
+
+fn list(sequences: &[u32]) -> String {
+    let numbers: Vec<String> = sequences.iter().map(u32::to_string).collect();
+    numbers.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(parts: &[Vec<u8>], order: &[usize]) -> Vec<(usize, Vec<u8>)> {
+        order.iter().map(|&i| (i, parts[i].clone())).collect()
+    }
+
+    #[test]
+    fn test_split_then_reassemble_in_any_order() {
+        let data = b"a message long enough to split".to_vec();
+        let parts = split(&data, 4).unwrap();
+        assert_eq!(parts.len(), 4);
+
+        let reassembled = reassemble(items(&parts, &[2, 0, 3, 1])).unwrap();
+        assert_eq!(reassembled, [(2, data)]);
+    }
+
+    #[test]
+    fn test_whole_and_split_messages_mix() {
+        let first = split(b"first", 2).unwrap();
+        let second = split(b"second", 3).unwrap();
+        let items = vec![
+            ("plain", b"plain".to_vec()),
+            ("first", first[1].clone()),
+            ("second", second[0].clone()),
+            ("first", first[0].clone()),
+            ("second", second[2].clone()),
+            ("second", second[1].clone()),
+        ];
+        let reassembled = reassemble(items).unwrap();
+        assert_eq!(
+            reassembled,
+            [
+                ("plain", b"plain".to_vec()),
+                ("first", b"first".to_vec()),
+                ("second", b"second".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_missing_and_duplicated_parts() {
+        let parts = split(b"0123456789", 4).unwrap();
+        let result = reassemble(items(&parts, &[0, 1, 1]));
+        let Err(Error::InvalidParts { reason, .. }) = result else {
+            panic!("expected InvalidParts, got {:?}", result);
+        };
+        assert_eq!(reason, "missing part(s) 2, 3 of 4, duplicated part(s) 1");
+    }
+
+    #[test]
+    fn test_untrusted_total_is_not_walked() {
+        let parts = split(b"0123456789", 2).unwrap();
+        let mut envelope = Envelope::from_bytes(&parts[0]).unwrap();
+        envelope.part.as_mut().unwrap().total = u32::MAX;
+        let result = reassemble(vec![(0, envelope.to_bytes())]);
+        let Err(Error::InvalidParts { reason, .. }) = result else {
+            panic!("expected InvalidParts, got {:?}", result);
+        };
+        assert_eq!(
+            reason,
+            "missing 4294967294 of 4294967295 parts, starting with 1, 2, 3, 4, 5, 6, 7, 8"
+        );
+    }
+
+    #[test]
+    fn test_detects_tampered_part() {
+        let parts = split(b"0123456789", 2).unwrap();
+        let mut envelope = Envelope::from_bytes(&parts[1]).unwrap();
+        envelope.payload[0] ^= 1;
+        let items = vec![(0, parts[0].clone()), (1, envelope.to_bytes())];
+        assert!(matches!(reassemble(items), Err(Error::InvalidParts { .. })));
+    }
+
+    #[test]
+    fn test_more_parts_than_bytes() {
+        let parts = split(b"ab", 5).unwrap();
+        assert_eq!(parts.len(), 5);
+        assert_eq!(reassemble(items(&parts, &[4, 3, 2, 1, 0])).unwrap()[0].1, b"ab");
+        assert!(split(b"ab", 0).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+//! Decoded pixels, for looking at what steganography did to an image.
+//!
+//! [`decode`] turns the image data into plain samples: palette indices are looked up in
+//! `PLTE`, samples under 8 bits are scaled up to 8, and `tRNS` becomes an alpha channel.
+//! 16-bit images keep their 16-bit samples. The result can be written as raw RGBA, as
+//! netpbm PGM/PPM or as PAM.
+
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::idat::Scanlines;
+use crate::ihdr::ColorType;
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// A file format for [`Pixels::write`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// RGBA samples with no header, 8 bits each or 16 bits big endian.
+    Raw,
+    /// Binary PGM for grayscale images and PPM for color ones. Netpbm has no alpha, so
+    /// it is dropped.
+    Pnm,
+    /// PAM, which keeps the alpha channel.
+    Pam,
+}
+
+impl PixelFormat {
+    /// The format matching a file extension: `raw` or `rgba`, `pgm`, `ppm` or `pnm`, and
+    /// `pam`.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "raw" | "rgba" => Some(PixelFormat::Raw),
+            "pgm" | "ppm" | "pnm" => Some(PixelFormat::Pnm),
+            "pam" => Some(PixelFormat::Pam),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for PixelFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "raw" => Ok(PixelFormat::Raw),
+            "pnm" | "pgm" | "ppm" => Ok(PixelFormat::Pnm),
+            "pam" => Ok(PixelFormat::Pam),
+            _ => Err(Error::InvalidPixelFormat { format: s.to_string() }),
+        }
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PixelFormat::Raw => write!(f, "raw RGBA"),
+            PixelFormat::Pnm => write!(f, "PNM"),
+            PixelFormat::Pam => write!(f, "PAM"),
+        }
+    }
+}
+
+/// Decoded samples, row after row, `channels` per pixel: gray, gray and alpha, RGB or
+/// RGBA. 16-bit samples are stored big endian.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pixels {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    /// 8 or 16.
+    pub bit_depth: u8,
+    pub data: Vec<u8>,
+}
+
+/// Decodes the pixels of `png`.
+pub fn decode(png: &Png) -> Result<Pixels> {
+    let scanlines = Scanlines::read(png)?;
+    let ihdr = *scanlines.ihdr();
+    let transparency = png.chunk_by_type("tRNS").map(|chunk| chunk.data());
+    let bit_depth = if ihdr.bit_depth == 16 { 16 } else { 8 };
+    let mut pixels = Pixels {
+        width: ihdr.width,
+        height: ihdr.height,
+        channels: 0,
+        bit_depth,
+        data: Vec::new(),
+    };
+
+    if ihdr.color_type == ColorType::Indexed {
+        let palette = png
+            .chunk_by_type("PLTE")
+            .ok_or_else(|| Error::ChunkNotFound { chunk_type: "PLTE".to_string() })?
+            .data();
+        let alpha = transparency.unwrap_or_default();
+        pixels.channels = if transparency.is_some() { 4 } else { 3 };
+        for (x, y) in positions(ihdr.width, ihdr.height) {
+            let index = scanlines.sample(x, y, 0).unwrap() as usize;
+            let color = palette.get(index * 3..index * 3 + 3).ok_or_else(|| Error::InvalidImageData {
+                reason: format!("palette index {} is past the {} colors of PLTE", index, palette.len() / 3),
+            })?;
+            pixels.data.extend_from_slice(color);
+            if transparency.is_some() {
+                pixels.data.push(*alpha.get(index).unwrap_or(&255));
+            }
+        }
+        return Ok(pixels);
+    }
+
+    let channels = ihdr.color_type.channels();
+    // A single fully transparent color, one sample per channel, for images without alpha.
+    let transparent: Option<Vec<u16>> = match (ihdr.color_type, transparency) {
+        (ColorType::Grayscale | ColorType::Rgb, Some(data)) if data.len() == channels as usize * 2 => {
+            Some(data.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect())
+        }
+        _ => None,
+    };
+    pixels.channels = channels + transparent.is_some() as u8;
+    let max = (1u32 << ihdr.bit_depth) - 1;
+    let mut samples = Vec::with_capacity(channels as usize);
+    for (x, y) in positions(ihdr.width, ihdr.height) {
+        samples.clear();
+        samples.extend((0..channels).map(|channel| scanlines.sample(x, y, channel).unwrap()));
+        let alpha = transparent.as_ref().map(|color| if *color == samples { 0 } else { max as u16 });
+        for &sample in samples.iter().chain(&alpha) {
+            match bit_depth {
+                16 => pixels.data.extend_from_slice(&sample.to_be_bytes()),
+                _ => pixels.data.push((sample as u32 * 255 / max) as u8),
+            }
+        }
+    }
+    Ok(pixels)
+}
+
+fn positions(width: u32, height: u32) -> impl Iterator<Item = (u32, u32)> {
+    (0..height).flat_map(move |y| (0..width).map(move |x| (x, y)))
+}
+
+impl Pixels {
+    pub fn has_alpha(&self) -> bool {
+        matches!(self.channels, 2 | 4)
+    }
+
+    fn sample_bytes(&self) -> usize {
+        self.bit_depth as usize / 8
+    }
+
+    /// The same pixels with `channels` samples each: gray is repeated for color, and a
+    /// missing alpha channel is opaque. Extra channels are dropped.
+    pub fn convert(&self, channels: u8) -> Pixels {
+        let bytes = self.sample_bytes();
+        let opaque = vec![0xff; bytes];
+        let mut data = Vec::with_capacity(self.width as usize * self.height as usize * channels as usize * bytes);
+        for pixel in self.data.chunks_exact(self.channels as usize * bytes) {
+            let sample = |i: usize| &pixel[i * bytes..(i + 1) * bytes];
+            let color: &[&[u8]] = match self.channels {
+                1 | 2 => &[sample(0), sample(0), sample(0)],
+                _ => &[sample(0), sample(1), sample(2)],
+            };
+            let alpha = if self.has_alpha() { sample(self.channels as usize - 1) } else { &opaque };
+            let wanted: &[&[u8]] = match channels {
+                1 => &[&pixel[..bytes]],
+                2 => &[&pixel[..bytes], alpha],
+                3 => color,
+                _ => &[color[0], color[1], color[2], alpha],
+            };
+            for sample in wanted {
+                data.extend_from_slice(sample);
+            }
+        }
+        Pixels { channels, data, ..*self }
+    }
+
+    /// Writes the pixels as `format`.
+    pub fn write<W: Write>(&self, format: PixelFormat, mut writer: W) -> Result<()> {
+        let max = (1u32 << self.bit_depth) - 1;
+        match format {
+            PixelFormat::Raw => writer.write_all(&self.convert(4).data)?,
+            PixelFormat::Pnm => {
+                let pixels = if self.has_alpha() { self.convert(self.channels - 1) } else { self.clone() };
+                let magic = if pixels.channels == 1 { "P5" } else { "P6" };
+                write!(writer, "{}\n{} {}\n{}\n", magic, self.width, self.height, max)?;
+                writer.write_all(&pixels.data)?;
+            }
+            PixelFormat::Pam => {
+                let tuple_type = match self.channels {
+                    1 => "GRAYSCALE",
+                    2 => "GRAYSCALE_ALPHA",
+                    3 => "RGB",
+                    _ => "RGB_ALPHA",
+                };
+                write!(
+                    writer,
+                    "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+                    self.width, self.height, self.channels, max, tuple_type
+                )?;
+                writer.write_all(&self.data)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::idat::tests::{encode_image, testing_image};
+    use crate::ihdr::{Ihdr, Interlace};
+
+    fn header(width: u32, height: u32, bit_depth: u8, color_type: ColorType) -> Ihdr {
+        Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace: Interlace::None,
+        }
+    }
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_rgb() {
+        let pixels = decode(&testing_image(4, 2)).unwrap();
+        assert_eq!((pixels.channels, pixels.bit_depth), (3, 8));
+        assert_eq!(pixels.data[..6], [0, 7, 14, 21, 28, 35]);
+        assert_eq!(pixels.convert(4).data[..8], [0, 7, 14, 255, 21, 28, 35, 255]);
+    }
+
+    #[test]
+    fn test_palette_with_transparency() {
+        let ihdr = header(4, 1, 2, ColorType::Indexed);
+        let palette = chunk("PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        let png = encode_image(ihdr, vec![0b00_01_10_01], vec![palette.clone()]);
+        let pixels = decode(&png).unwrap();
+        assert_eq!(pixels.channels, 3);
+        assert_eq!(pixels.data, [255, 0, 0, 0, 255, 0, 0, 0, 255, 0, 255, 0]);
+
+        let png = encode_image(ihdr, vec![0b00_01_10_01], vec![palette.clone(), chunk("tRNS", &[0])]);
+        let pixels = decode(&png).unwrap();
+        assert_eq!(pixels.channels, 4);
+        assert_eq!(pixels.data[..8], [255, 0, 0, 0, 0, 255, 0, 255]);
+
+        let png = encode_image(ihdr, vec![0b11_00_00_00], vec![palette]);
+        assert!(matches!(decode(&png), Err(Error::InvalidImageData { .. })));
+    }
+
+    #[test]
+    fn test_sixteen_bit_gray_with_transparency() {
+        let ihdr = header(2, 1, 16, ColorType::Grayscale);
+        let png = encode_image(ihdr, vec![0x12, 0x34, 0xab, 0xcd], vec![chunk("tRNS", &[0x12, 0x34])]);
+        let pixels = decode(&png).unwrap();
+        assert_eq!((pixels.channels, pixels.bit_depth), (2, 16));
+        assert_eq!(pixels.data, [0x12, 0x34, 0, 0, 0xab, 0xcd, 0xff, 0xff]);
+        assert_eq!(pixels.convert(4).data[8..], [0xab, 0xcd, 0xab, 0xcd, 0xab, 0xcd, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_low_bit_depth_is_scaled() {
+        let png = encode_image(header(4, 1, 2, ColorType::Grayscale), vec![0b00_01_10_11], Vec::new());
+        assert_eq!(decode(&png).unwrap().data, [0, 85, 170, 255]);
+    }
+
+    #[test]
+    fn test_formats() {
+        let pixels = Pixels { width: 1, height: 2, channels: 2, bit_depth: 8, data: vec![10, 20, 30, 40] };
+        let mut out = Vec::new();
+        pixels.write(PixelFormat::Pnm, &mut out).unwrap();
+        assert_eq!(out, b"P5\n1 2\n255\n\x0a\x1e");
+
+        let mut out = Vec::new();
+        pixels.write(PixelFormat::Pam, &mut out).unwrap();
+        let header = "P7\nWIDTH 1\nHEIGHT 2\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n";
+        assert_eq!(out[..header.len()], *header.as_bytes());
+        assert_eq!(out[header.len()..], [10, 20, 30, 40]);
+
+        let mut out = Vec::new();
+        pixels.write(PixelFormat::Raw, &mut out).unwrap();
+        assert_eq!(out, [10, 10, 10, 20, 30, 30, 30, 40]);
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(PixelFormat::from_str("ppm").unwrap(), PixelFormat::Pnm);
+        assert_eq!(PixelFormat::from_extension("PAM"), Some(PixelFormat::Pam));
+        assert!(PixelFormat::from_str("bmp").is_err());
+    }
+}
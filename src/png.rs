@@ -1,65 +1,55 @@
 use std::fmt;
 use std::str::FromStr;
-use std::{convert::TryFrom, str::from_utf8};
+use std::convert::TryFrom;
 use crate::chunk_type::ChunkType;
-use crate::{chunk::Chunk, chunk_type, Error, Result};
-use std::io::{BufReader, Read};
-use std::path::PathBuf;
-use std::fs::{read as read_file, File};
+use crate::ihdr::Ihdr;
+use crate::reader::ChunkReader;
+use crate::writer::ChunkWriter;
+use crate::{chunk::Chunk, Error, Result};
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+use std::fs::File;
 use std::io::Write;
 
-#[derive(Debug)]
-pub struct PngError {
-    message: String,
+pub struct Png {
+    chunks: Vec<Chunk>,
 }
 
-impl PngError {
-    fn boxed(message: String) -> Box<Self> {
-        Box::new(Self {message})
-    }
+/// Where [`Png::insert_chunk`] places a new chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    /// Right before `IEND`, or at the end if there is no `IEND`.
+    BeforeIend,
+    /// Right after `IHDR`.
+    AfterIhdr,
+    /// Right before the first `IDAT`.
+    BeforeFirstIdat,
+    /// At the given index in [`Png::chunks`], after the first chunk and no later than
+    /// `IEND`.
+    Index(usize),
 }
 
-impl fmt::Display for PngError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Bad chunk: {}", self.message)
+impl FromStr for Position {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "before-iend" => Ok(Position::BeforeIend),
+            "after-ihdr" => Ok(Position::AfterIhdr),
+            "before-idat" => Ok(Position::BeforeFirstIdat),
+            _ => s
+                .parse()
+                .map(Position::Index)
+                .map_err(|_| Error::InvalidPosition { position: s.to_string() }),
+        }
     }
 }
 
-impl std::error::Error for PngError {}
-
-pub struct Png {
-    chunks: Vec<Chunk>,
-}
-
 impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self> {
-        let mut reader = BufReader::new(value);
-        let mut header: [u8; 8] = [0; 8];
-        reader.read_exact(&mut header)?;
-        
-        if header != Png::STANDARD_HEADER {
-            return Err(PngError::boxed(format!(
-                "Signature is not valid"
-            )));
-        }
-
-        let mut chunks = Vec::new();
-        let mut length = [0; 4];
-        while let Ok(()) = reader.read_exact(&mut length) {
-            let pos = 4 + u32::from_be_bytes(length) + 4;
-            let mut buffer = vec![0; usize::try_from(pos)?];
-            reader.read_exact(&mut buffer)?;
-            let all_bytes: Vec<u8> = length
-                .iter()
-                .copied()
-                .chain(buffer.into_iter())
-                .collect();
-            let chunk = Chunk::try_from(all_bytes.as_slice())?;
-            chunks.push(chunk);
-        }
-        Ok(Png::from_chunks(chunks))
+        Png::from_reader(value)
     }
 }
 
@@ -73,55 +63,131 @@ impl Png {
     pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
-        Png { chunks: chunks }
+        Png { chunks }
     }
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
+    pub fn insert_chunk(&mut self, position: Position, chunk: Chunk) -> Result<()> {
+        let index = match position {
+            Position::BeforeIend => self.position_of(b"IEND").unwrap_or(self.chunks.len()),
+            Position::AfterIhdr => self.required_position_of(b"IHDR")? + 1,
+            Position::BeforeFirstIdat => self.required_position_of(b"IDAT")?,
+            Position::Index(index)
+                if index >= 1 && index <= self.position_of(b"IEND").unwrap_or(self.chunks.len()) =>
+            {
+                index
+            }
+            Position::Index(index) => {
+                return Err(Error::IndexOutOfRange { index, len: self.chunks.len() })
+            }
+        };
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+    fn position_of(&self, chunk_type: &[u8; 4]) -> Option<usize> {
+        self.chunks
+            .iter()
+            .position(|c| c.chunk_type().bytes() == *chunk_type)
+    }
+    fn required_position_of(&self, chunk_type: &[u8; 4]) -> Result<usize> {
+        self.position_of(chunk_type).ok_or_else(|| Error::ChunkNotFound {
+            chunk_type: String::from_utf8_lossy(chunk_type).into_owned(),
+        })
+    }
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
         if let Some(pos) = self
             .chunks
             .iter()
-            .position(|c| *c.chunk_type() == ChunkType::from_str(chunk_type).unwrap())
+            .position(|c| *c.chunk_type() == chunk_type)
         {
             Ok(self.chunks.remove(pos))
         } else {
-            Err(PngError::boxed(format!("No matching chunk found")))
+            Err(Error::ChunkNotFound { chunk_type: chunk_type.to_string() })
         }
     }
+    /// Keeps only the chunks for which `keep` returns true and returns the others, in order.
+    pub fn retain_chunks<F: FnMut(&Chunk) -> bool>(&mut self, mut keep: F) -> Vec<Chunk> {
+        let (kept, removed) = self.chunks.drain(..).partition(|c| keep(c));
+        self.chunks = kept;
+        removed
+    }
+    /// Replaces every `IDAT` chunk with `chunks`, placed where the first `IDAT` was.
+    pub fn replace_image_data(&mut self, chunks: Vec<Chunk>) -> Result<()> {
+        let index = self.required_position_of(b"IDAT")?;
+        self.retain_chunks(|c| &c.chunk_type().bytes() != b"IDAT");
+        self.chunks.splice(index..index, chunks);
+        Ok(())
+    }
     fn header(&self) -> &[u8; 8] {
         &Png::STANDARD_HEADER
     }
     pub fn chunks(&self) -> &[Chunk] {
-        &self.chunks.as_slice()
+        self.chunks.as_slice()
+    }
+    /// Pairs every chunk with its byte offset in [`Png::as_bytes`].
+    pub fn chunks_with_offsets(&self) -> impl Iterator<Item = (usize, &Chunk)> {
+        self.chunks.iter().scan(Png::STANDARD_HEADER.len(), |offset, chunk| {
+            let chunk_offset = *offset;
+            *offset += 12 + chunk.length() as usize;
+            Some((chunk_offset, chunk))
+        })
+    }
+    /// Parses the `IHDR` chunk, which the spec requires to come first.
+    pub fn header_info(&self) -> Result<Ihdr> {
+        match self.chunks.first() {
+            Some(chunk) if &chunk.chunk_type().bytes() == b"IHDR" => Ihdr::try_from(chunk),
+            _ => Err(Error::ChunkNotFound { chunk_type: "IHDR".to_string() }),
+        }
+    }
+    /// Replaces the `IHDR` chunk with `ihdr`.
+    pub fn set_header(&mut self, ihdr: &Ihdr) -> Result<()> {
+        match self.chunks.first_mut() {
+            Some(chunk) if &chunk.chunk_type().bytes() == b"IHDR" => {
+                *chunk = ihdr.to_chunk();
+                Ok(())
+            }
+            _ => Err(Error::ChunkNotFound { chunk_type: "IHDR".to_string() }),
+        }
     }
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
-        // for (i, chunk) in self.chunks.iter().enumerate() {
-        //     if chunk.chunk_type == ChunkType::from_str(chunk_type).unwrap() {
-        //         return Some(chunk);
-        //     }
-        // }
-        // None
-        self.chunks.iter().find(|c| *c.chunk_type() == ChunkType::from_str(chunk_type).unwrap())
+        self.chunks_by_type(chunk_type).next()
+    }
+    /// Every chunk of type `chunk_type`, in file order.
+    pub fn chunks_by_type(&self, chunk_type: &str) -> impl Iterator<Item = &Chunk> {
+        let chunk_type = ChunkType::from_str(chunk_type).ok();
+        self.chunks
+            .iter()
+            .filter(move |c| Some(c.chunk_type()) == chunk_type.as_ref())
     }
     pub fn as_bytes(&self) -> Vec<u8> {
-        let chunks: Vec<u8> = self.chunks.iter().map(|c| c.as_bytes()).flatten().collect();
+        let chunks: Vec<u8> = self.chunks.iter().flat_map(|c| c.as_bytes()).collect();
         self.header()
             .iter()
             .chain(chunks.iter())
             .copied()
             .collect()
     }
-    pub fn from_file(path: PathBuf) -> Result<Self> {
-        let file = read_file(path)?;
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let chunks = ChunkReader::new(reader)?
+            .full_chunks()
+            .collect::<Result<Vec<Chunk>>>()?;
 
-        Png::try_from(file.as_slice())
+        Ok(Png::from_chunks(chunks))
+    }
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Png::from_reader(BufReader::new(File::open(path)?))
+    }
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<W> {
+        let mut writer = ChunkWriter::new(writer)?;
+        for chunk in &self.chunks {
+            writer.write_chunk(chunk)?;
+        }
+        writer.finish()
     }
-    pub fn write_file(&self, path: PathBuf) -> Result<()> {
-        let mut file =
-            File::create(path).map_err(|e| PngError::boxed(format!("Error creating file")))?;
-        file.write(self.as_bytes().as_slice())
-            .map_err(|e| PngError::boxed(format!("Error writing to file")))?;
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))?;
 
         Ok(())
     }
@@ -133,7 +199,6 @@ mod tests {
     use super::*;
     use crate::chunk_type::ChunkType;
     use crate::chunk::Chunk;
-    use std::str::FromStr;
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
@@ -199,7 +264,27 @@ mod tests {
 
         let png = Png::try_from(bytes.as_ref());
 
-        assert!(png.is_err());
+        assert!(matches!(png, Err(Error::BadSignature)));
+    }
+
+    #[test]
+    fn test_crc_mismatch_reports_offset() {
+        let mut bytes = PNG_FILE.to_vec();
+        // Last byte of the IHDR CRC
+        bytes[32] ^= 0xff;
+
+        let png = Png::try_from(bytes.as_ref());
+
+        assert!(matches!(png, Err(Error::CrcMismatch { offset: 8, .. })));
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let bytes = &PNG_FILE[..PNG_FILE.len() - 2];
+
+        let png = Png::try_from(bytes);
+
+        assert!(matches!(png, Err(Error::TruncatedChunk { offset: 4791 })));
     }
 
     #[test]
@@ -241,6 +326,18 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("FrSt", "I am the second first chunk").unwrap());
+        let messages: Vec<String> = png
+            .chunks_by_type("FrSt")
+            .map(|c| c.data_as_string().unwrap())
+            .collect();
+        assert_eq!(messages, ["I am the first chunk", "I am the second first chunk"]);
+        assert_eq!(png.chunks_by_type("NoNe").count(), 0);
+    }
+
     #[test]
     fn test_append_chunk() {
         let mut png = testing_png();
@@ -250,6 +347,88 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_insert_chunk_before_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(Position::BeforeIend, chunk_from_strings("TeSt", "Message").unwrap())
+            .unwrap();
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types[types.len() - 2..], ["TeSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_insert_chunk_before_iend_without_iend() {
+        let mut png = testing_png();
+        png.insert_chunk(Position::BeforeIend, chunk_from_strings("TeSt", "Message").unwrap())
+            .unwrap();
+        assert_eq!(&png.chunks()[3].chunk_type().to_string(), "TeSt");
+    }
+
+    #[test]
+    fn test_insert_chunk_after_ihdr() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(Position::AfterIhdr, chunk_from_strings("TeSt", "Message").unwrap())
+            .unwrap();
+        assert_eq!(&png.chunks()[0].chunk_type().to_string(), "IHDR");
+        assert_eq!(&png.chunks()[1].chunk_type().to_string(), "TeSt");
+    }
+
+    #[test]
+    fn test_insert_chunk_before_first_idat() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(Position::BeforeFirstIdat, chunk_from_strings("TeSt", "Message").unwrap())
+            .unwrap();
+        let index = png.chunks().iter().position(|c| c.chunk_type().to_string() == "TeSt").unwrap();
+        assert_eq!(&png.chunks()[index + 1].chunk_type().to_string(), "IDAT");
+    }
+
+    #[test]
+    fn test_insert_chunk_missing_anchor() {
+        let mut png = testing_png();
+        let result = png.insert_chunk(Position::AfterIhdr, chunk_from_strings("TeSt", "Message").unwrap());
+        assert!(matches!(result, Err(Error::ChunkNotFound { .. })));
+    }
+
+    #[test]
+    fn test_insert_chunk_at_index() {
+        let mut png = testing_png();
+        png.insert_chunk(Position::Index(1), chunk_from_strings("TeSt", "Message").unwrap())
+            .unwrap();
+        assert_eq!(&png.chunks()[1].chunk_type().to_string(), "TeSt");
+
+        let result = png.insert_chunk(Position::Index(9), chunk_from_strings("TeSt", "Message").unwrap());
+        assert!(matches!(result, Err(Error::IndexOutOfRange { index: 9, len: 4 })));
+    }
+
+    #[test]
+    fn test_insert_chunk_index_stays_between_first_chunk_and_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let len = png.chunks().len();
+        let iend = len - 1;
+        let result = png.insert_chunk(Position::Index(0), chunk_from_strings("TeSt", "Message").unwrap());
+        assert!(matches!(result, Err(Error::IndexOutOfRange { index: 0, .. })));
+        let result = png.insert_chunk(Position::Index(len), chunk_from_strings("TeSt", "Message").unwrap());
+        assert!(matches!(result, Err(Error::IndexOutOfRange { .. })));
+
+        png.insert_chunk(Position::Index(1), chunk_from_strings("TeSt", "Message").unwrap())
+            .unwrap();
+        png.insert_chunk(Position::Index(iend + 1), chunk_from_strings("TeSt", "Message").unwrap())
+            .unwrap();
+        assert_eq!(&png.chunks()[0].chunk_type().to_string(), "IHDR");
+        assert_eq!(&png.chunks()[1].chunk_type().to_string(), "TeSt");
+        assert_eq!(&png.chunks()[iend + 1].chunk_type().to_string(), "TeSt");
+        assert_eq!(&png.chunks()[iend + 2].chunk_type().to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_position_from_str() {
+        assert_eq!(Position::from_str("before-iend").unwrap(), Position::BeforeIend);
+        assert_eq!(Position::from_str("after-ihdr").unwrap(), Position::AfterIhdr);
+        assert_eq!(Position::from_str("before-idat").unwrap(), Position::BeforeFirstIdat);
+        assert_eq!(Position::from_str("3").unwrap(), Position::Index(3));
+        assert!(Position::from_str("middle").is_err());
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -259,6 +438,56 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_retain_chunks() {
+        let mut png = testing_png();
+        let removed = png.retain_chunks(|c| c.chunk_type().is_critical());
+        assert_eq!(removed.len(), 1);
+        assert_eq!(&removed[0].chunk_type().to_string(), "miDl");
+        assert_eq!(png.chunks().len(), 2);
+    }
+
+    #[test]
+    fn test_remove_missing_chunk() {
+        let mut png = testing_png();
+        let removed = png.remove_chunk("TeSt");
+        assert!(matches!(removed, Err(Error::ChunkNotFound { .. })));
+    }
+
+    #[test]
+    fn test_chunks_with_offsets() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let offsets: Vec<usize> = png.chunks_with_offsets().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets[..2], [8, 33]);
+        assert_eq!(*offsets.last().unwrap(), 4791);
+    }
+
+    #[test]
+    fn test_header_info() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.header_info().unwrap();
+        assert_eq!(ihdr.to_string(), "50x50, 8-bit RGBA, non-interlaced");
+    }
+
+    #[test]
+    fn test_header_info_missing() {
+        let png = testing_png();
+        assert!(matches!(png.header_info(), Err(Error::ChunkNotFound { .. })));
+    }
+
+    #[test]
+    fn test_replace_image_data() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let first_idat = png.position_of(b"IDAT").unwrap();
+        let idat = chunk_from_strings("IDAT", "new pixels").unwrap();
+        png.replace_image_data(vec![idat.clone(), idat]).unwrap();
+
+        assert_eq!(png.position_of(b"IDAT"), Some(first_idat));
+        assert_eq!(png.chunks_by_type("IDAT").count(), 2);
+        assert_eq!(png.chunks()[first_idat + 1].data(), b"new pixels");
+        assert!(testing_png().replace_image_data(Vec::new()).is_err());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -273,6 +502,13 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_write_to() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let written = png.write_to(Vec::new()).unwrap();
+        assert_eq!(written, PNG_FILE.to_vec());
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
@@ -0,0 +1,274 @@
+//! Reading PNG chunks one at a time from any [`Read`] source.
+
+use std::io::{self, ErrorKind, Read};
+
+use crate::chunk::{Chunk, MAXIMUM_LENGTH};
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// A chunk yielded by [`ChunkReader`].
+#[derive(Debug, Clone)]
+pub struct StreamedChunk {
+    index: usize,
+    offset: usize,
+    length: u32,
+    chunk_type: ChunkType,
+    crc: u32,
+    chunk: Option<Chunk>,
+}
+
+impl StreamedChunk {
+    /// Position of the chunk in the stream, counting from 0.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+    /// Byte offset of the chunk from the start of the stream, signature included.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    /// The full chunk, or `None` if its data was skipped.
+    pub fn chunk(&self) -> Option<&Chunk> {
+        self.chunk.as_ref()
+    }
+    pub fn into_chunk(self) -> Option<Chunk> {
+        self.chunk
+    }
+}
+
+type SkipData = dyn Fn(&ChunkType) -> bool;
+
+/// Iterates over the chunks of a PNG stream without loading the whole file.
+///
+/// Only one chunk is held in memory at a time, and chunks whose data is skipped
+/// (see [`ChunkReader::skip_data`]) are never held at all. Skipped chunks are not
+/// CRC checked. Iteration stops after the first error.
+pub struct ChunkReader<R> {
+    reader: R,
+    index: usize,
+    offset: usize,
+    skip: Option<Box<SkipData>>,
+    done: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Reads and checks the PNG signature.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header).map_err(|_| Error::BadSignature)?;
+        if header != Png::STANDARD_HEADER {
+            return Err(Error::BadSignature);
+        }
+        Ok(ChunkReader {
+            reader,
+            index: 0,
+            offset: header.len(),
+            skip: None,
+            done: false,
+        })
+    }
+
+    /// Skips over the data of chunks for which `skip` returns true.
+    pub fn skip_data<F: Fn(&ChunkType) -> bool + 'static>(mut self, skip: F) -> Self {
+        self.skip = Some(Box::new(skip));
+        self
+    }
+
+    /// Yields the chunks whose data was read, dropping the skipped ones.
+    pub fn full_chunks(self) -> impl Iterator<Item = Result<Chunk>> {
+        self.filter_map(|chunk| chunk.map(StreamedChunk::into_chunk).transpose())
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<StreamedChunk>> {
+        let offset = self.offset;
+        let truncated = |e: io::Error| match e.kind() {
+            ErrorKind::UnexpectedEof => Error::TruncatedChunk { offset },
+            _ => Error::Io(e),
+        };
+
+        let mut buf = [0; 4];
+        let read = read_up_to(&mut self.reader, &mut buf)?;
+        if read == 0 {
+            return Ok(None);
+        } else if read < buf.len() {
+            return Err(Error::TruncatedChunk { offset });
+        }
+        let length = u32::from_be_bytes(buf);
+        if length > MAXIMUM_LENGTH {
+            return Err(Error::ChunkTooLong { offset, length });
+        }
+
+        self.reader.read_exact(&mut buf).map_err(truncated)?;
+        let chunk_type = ChunkType::try_from(buf)?;
+
+        let skip = self.skip.as_ref().is_some_and(|skip| skip(&chunk_type));
+        let data = if skip {
+            let skipped = io::copy(&mut (&mut self.reader).take(length.into()), &mut io::sink())?;
+            if skipped < length.into() {
+                return Err(Error::TruncatedChunk { offset });
+            }
+            None
+        } else {
+            // Grow with the data that actually arrives rather than trusting the length.
+            let mut data = Vec::new();
+            (&mut self.reader).take(length.into()).read_to_end(&mut data)?;
+            if data.len() < length as usize {
+                return Err(Error::TruncatedChunk { offset });
+            }
+            Some(data)
+        };
+
+        self.reader.read_exact(&mut buf).map_err(truncated)?;
+        let crc = u32::from_be_bytes(buf);
+
+        let chunk = match data {
+            Some(data) => {
+                let chunk = Chunk::new(chunk_type.clone(), data);
+                if chunk.crc() != crc {
+                    return Err(Error::CrcMismatch {
+                        offset,
+                        expected: chunk.crc(),
+                        actual: crc,
+                    });
+                }
+                Some(chunk)
+            }
+            None => None,
+        };
+
+        let streamed = StreamedChunk {
+            index: self.index,
+            offset,
+            length,
+            chunk_type,
+            crc,
+            chunk,
+        };
+        self.index += 1;
+        self.offset += 12 + length as usize;
+        Ok(Some(streamed))
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = Result<StreamedChunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.read_chunk().transpose();
+        if !matches!(next, Some(Ok(_))) {
+            self.done = true;
+        }
+        next
+    }
+}
+
+/// Like `read_exact`, but stops at end of input and returns how much was read.
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::Io(e)),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = [
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"I am the first chunk".to_vec()),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![1, 2, 3, 4]),
+            Chunk::new(ChunkType::from_str("LASt").unwrap(), b"I am the last chunk".to_vec()),
+        ];
+        Png::from_chunks(chunks.to_vec()).as_bytes()
+    }
+
+    #[test]
+    fn test_reads_every_chunk() {
+        let bytes = testing_bytes();
+        let chunks: Vec<StreamedChunk> = ChunkReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].index(), 1);
+        assert_eq!(chunks[1].offset(), 8 + 12 + 20);
+        assert_eq!(chunks[1].chunk().unwrap().data(), [1, 2, 3, 4]);
+        assert_eq!(&chunks[2].chunk_type().to_string(), "LASt");
+    }
+
+    #[test]
+    fn test_skip_data() {
+        let bytes = testing_bytes();
+        let chunks: Vec<StreamedChunk> = ChunkReader::new(bytes.as_slice())
+            .unwrap()
+            .skip_data(|chunk_type| &chunk_type.bytes() == b"IDAT")
+            .collect::<Result<_>>()
+            .unwrap();
+        assert!(chunks[0].chunk().is_some());
+        assert!(chunks[1].chunk().is_none());
+        assert_eq!(chunks[1].length(), 4);
+        assert_eq!(chunks[2].chunk().unwrap().data(), b"I am the last chunk");
+    }
+
+    #[test]
+    fn test_bad_signature() {
+        let mut bytes = testing_bytes();
+        bytes[0] = 0;
+        assert!(matches!(ChunkReader::new(bytes.as_slice()), Err(Error::BadSignature)));
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let bytes = testing_bytes();
+        let last = ChunkReader::new(&bytes[..bytes.len() - 1]).unwrap().last().unwrap();
+        assert!(matches!(last, Err(Error::TruncatedChunk { offset: 56 })));
+    }
+
+    #[test]
+    fn test_truncated_huge_chunk() {
+        let mut bytes = testing_bytes()[..8 + 12 + 20].to_vec();
+        bytes.extend_from_slice(&0x7fff_ffffu32.to_be_bytes());
+        bytes.extend_from_slice(b"ruSt");
+        bytes.extend_from_slice(&[0; 400]);
+        let last = ChunkReader::new(bytes.as_slice()).unwrap().last().unwrap();
+        assert!(matches!(last, Err(Error::TruncatedChunk { offset: 40 })));
+    }
+
+    #[test]
+    fn test_invalid_chunk_type() {
+        let mut bytes = testing_bytes();
+        bytes[12..16].copy_from_slice(&[0xff, 0xfe, 0xfd, 0xfc]);
+        let first = ChunkReader::new(bytes.as_slice()).unwrap().next().unwrap();
+        assert!(matches!(first, Err(Error::InvalidChunkType { .. })));
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut bytes = testing_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        let results: Vec<Result<StreamedChunk>> = ChunkReader::new(bytes.as_slice()).unwrap().collect();
+        assert_eq!(results.len(), 3);
+        assert!(matches!(results[2], Err(Error::CrcMismatch { offset: 56, .. })));
+    }
+}
@@ -0,0 +1,352 @@
+//! Tamper-evident seals over the critical chunks of an image.
+//!
+//! A seal records a digest of the `IHDR` chunk, the `PLTE` chunk if there is one, and
+//! all `IDAT` chunks together, signed with an Ed25519 key. It is stored in a
+//! [`SEAL_CHUNK_TYPE`] chunk, which is ancillary and unsafe to copy: editors that change
+//! the image are expected to drop it rather than carry a stale seal along.
+//!
+//! The chunk data is a version byte, the [`SealHash`] id, a count byte, then for each
+//! sealed chunk type its 4 bytes and 32 byte digest, and finally the 64 byte signature
+//! over everything before it. Each digest covers the length and data of every chunk of
+//! that type, in file order.
+
+use std::fmt;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::keys::{SigningKey, VerifyingKey};
+use crate::png::{Png, Position};
+use crate::{Error, Result};
+
+/// The type of the chunk holding the seal.
+pub const SEAL_CHUNK_TYPE: &str = "seAL";
+
+const VERSION: u8 = 1;
+const SEALED_TYPES: [&str; 3] = ["IHDR", "PLTE", "IDAT"];
+const SIGNATURE_CONTEXT: &[u8] = b"pngme seal v1";
+
+/// The hash function a seal's digests are made with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SealHash {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl SealHash {
+    fn id(self) -> u8 {
+        match self {
+            SealHash::Sha256 => 1,
+            SealHash::Blake3 => 2,
+        }
+    }
+
+    fn digest<'a, I: Iterator<Item = &'a Chunk>>(self, chunks: I) -> [u8; 32] {
+        let mut sha256 = Sha256::new();
+        let mut blake3 = blake3::Hasher::new();
+        for chunk in chunks {
+            let length = chunk.length().to_be_bytes();
+            match self {
+                SealHash::Sha256 => {
+                    sha256.update(length);
+                    sha256.update(chunk.data());
+                }
+                SealHash::Blake3 => {
+                    blake3.update(&length);
+                    blake3.update(chunk.data());
+                }
+            }
+        }
+        match self {
+            SealHash::Sha256 => sha256.finalize().into(),
+            SealHash::Blake3 => blake3.finalize().into(),
+        }
+    }
+}
+
+impl TryFrom<u8> for SealHash {
+    type Error = Error;
+
+    fn try_from(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(SealHash::Sha256),
+            2 => Ok(SealHash::Blake3),
+            _ => Err(invalid(&format!("unknown hash {}", id))),
+        }
+    }
+}
+
+impl FromStr for SealHash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sha256" => Ok(SealHash::Sha256),
+            "blake3" => Ok(SealHash::Blake3),
+            _ => Err(invalid(&format!("unknown hash {:?}, expected sha256 or blake3", s))),
+        }
+    }
+}
+
+impl fmt::Display for SealHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SealHash::Sha256 => write!(f, "SHA-256"),
+            SealHash::Blake3 => write!(f, "BLAKE3"),
+        }
+    }
+}
+
+/// How a sealed chunk type compares with the image it was checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestStatus {
+    Unchanged,
+    Changed,
+    /// Sealed, but no longer in the image.
+    Missing,
+    /// In the image, but not sealed.
+    Added,
+}
+
+impl fmt::Display for DigestStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DigestStatus::Unchanged => write!(f, "unchanged"),
+            DigestStatus::Changed => write!(f, "changed"),
+            DigestStatus::Missing => write!(f, "missing"),
+            DigestStatus::Added => write!(f, "added"),
+        }
+    }
+}
+
+/// The result of [`check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealReport {
+    pub hash: SealHash,
+    /// Whether the seal was signed by the trusted key. When it was not, the digests
+    /// can not be trusted either.
+    pub signature_valid: bool,
+    pub digests: Vec<(ChunkType, DigestStatus)>,
+}
+
+impl SealReport {
+    /// Whether the image is exactly as it was sealed.
+    pub fn is_intact(&self) -> bool {
+        self.signature_valid
+            && self.digests.iter().all(|(_, status)| *status == DigestStatus::Unchanged)
+    }
+}
+
+/// A parsed seal chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seal {
+    hash: SealHash,
+    digests: Vec<(ChunkType, [u8; 32])>,
+    signature: [u8; 64],
+}
+
+impl Seal {
+    /// Seals the critical chunks of `png` with `key`.
+    pub fn new(png: &Png, hash: SealHash, key: &SigningKey) -> Self {
+        let digests = digests(png, hash);
+        let signature = key.sign(&signed_message(hash, &digests));
+        Seal { hash, digests, signature }
+    }
+
+    pub fn hash(&self) -> SealHash {
+        self.hash
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = vec![VERSION, self.hash.id(), self.digests.len() as u8];
+        for (chunk_type, digest) in &self.digests {
+            data.extend_from_slice(&chunk_type.bytes());
+            data.extend_from_slice(digest);
+        }
+        data.extend_from_slice(&self.signature);
+        Chunk::new(ChunkType::from_str(SEAL_CHUNK_TYPE).unwrap(), data)
+    }
+
+    /// Compares the seal with the current contents of `png`.
+    pub fn check(&self, png: &Png, key: &VerifyingKey) -> SealReport {
+        let signature_valid = key.verify(&signed_message(self.hash, &self.digests), &self.signature);
+        let current = digests(png, self.hash);
+        let find = |digests: &[(ChunkType, [u8; 32])], chunk_type: &ChunkType| {
+            digests.iter().find(|(t, _)| t == chunk_type).map(|(_, digest)| *digest)
+        };
+
+        let digests = SEALED_TYPES
+            .iter()
+            .map(|t| ChunkType::from_str(t).unwrap())
+            .filter_map(|chunk_type| {
+                let status = match (find(&self.digests, &chunk_type), find(&current, &chunk_type)) {
+                    (None, None) => return None,
+                    (Some(sealed), Some(now)) if sealed == now => DigestStatus::Unchanged,
+                    (Some(_), Some(_)) => DigestStatus::Changed,
+                    (Some(_), None) => DigestStatus::Missing,
+                    (None, Some(_)) => DigestStatus::Added,
+                };
+                Some((chunk_type, status))
+            })
+            .collect();
+        SealReport { hash: self.hash, signature_valid, digests }
+    }
+}
+
+impl TryFrom<&Chunk> for Seal {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        let data = chunk.data();
+        let [version, hash, count, rest @ ..] = data else {
+            return Err(invalid("truncated header"));
+        };
+        if *version != VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        let hash = SealHash::try_from(*hash)?;
+        if rest.len() != *count as usize * 36 + 64 {
+            return Err(invalid("wrong length"));
+        }
+        let (entries, signature) = rest.split_at(rest.len() - 64);
+        let digests = entries
+            .chunks_exact(36)
+            .map(|entry| {
+                let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&entry[..4]).unwrap())?;
+                Ok((chunk_type, entry[4..].try_into().unwrap()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Seal {
+            hash,
+            digests,
+            signature: signature.try_into().unwrap(),
+        })
+    }
+}
+
+/// Seals `png`, replacing any previous seal. The seal goes right before `IEND`.
+pub fn seal(png: &mut Png, hash: SealHash, key: &SigningKey) -> Result<()> {
+    png.retain_chunks(|chunk| chunk.chunk_type().to_string() != SEAL_CHUNK_TYPE);
+    let seal = Seal::new(png, hash, key);
+    png.insert_chunk(Position::BeforeIend, seal.to_chunk())
+}
+
+/// Checks the seal of `png` against a trusted `key`.
+///
+/// Fails with [`Error::ChunkNotFound`] if the image was never sealed.
+pub fn check(png: &Png, key: &VerifyingKey) -> Result<SealReport> {
+    let chunk = png
+        .chunk_by_type(SEAL_CHUNK_TYPE)
+        .ok_or_else(|| Error::ChunkNotFound { chunk_type: SEAL_CHUNK_TYPE.to_string() })?;
+    Ok(Seal::try_from(chunk)?.check(png, key))
+}
+
+fn digests(png: &Png, hash: SealHash) -> Vec<(ChunkType, [u8; 32])> {
+    SEALED_TYPES
+        .iter()
+        .filter(|t| png.chunk_by_type(t).is_some())
+        .map(|t| (ChunkType::from_str(t).unwrap(), hash.digest(png.chunks_by_type(t))))
+        .collect()
+}
+
+fn signed_message(hash: SealHash, digests: &[(ChunkType, [u8; 32])]) -> Vec<u8> {
+    let mut message = SIGNATURE_CONTEXT.to_vec();
+    message.push(hash.id());
+    for (chunk_type, digest) in digests {
+        message.extend_from_slice(&chunk_type.bytes());
+        message.extend_from_slice(digest);
+    }
+    message
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidSeal { reason: reason.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::tests::testing_image;
+
+    fn statuses(report: &SealReport) -> Vec<(String, DigestStatus)> {
+        report.digests.iter().map(|(t, s)| (t.to_string(), *s)).collect()
+    }
+
+    #[test]
+    fn test_seal_then_check() {
+        for hash in [SealHash::Sha256, SealHash::Blake3] {
+            let key = SigningKey::generate();
+            let mut png = testing_image();
+            seal(&mut png, hash, &key).unwrap();
+            assert_eq!(&png.chunks()[3].chunk_type().to_string(), SEAL_CHUNK_TYPE);
+
+            let report = check(&png, &key.verifying_key()).unwrap();
+            assert!(report.is_intact());
+            assert_eq!(report.hash, hash);
+            assert_eq!(report.digests.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_detects_changed_pixels_and_added_palette() {
+        let key = SigningKey::generate();
+        let mut png = testing_image();
+        seal(&mut png, SealHash::Sha256, &key).unwrap();
+
+        let mut chunks = png.chunks().to_vec();
+        chunks[2] = Chunk::new(ChunkType::from_str("IDAT").unwrap(), b"mine".to_vec());
+        chunks.insert(1, Chunk::new(ChunkType::from_str("PLTE").unwrap(), vec![0; 3]));
+        let report = check(&Png::from_chunks(chunks), &key.verifying_key()).unwrap();
+
+        assert!(report.signature_valid);
+        assert!(!report.is_intact());
+        assert_eq!(
+            statuses(&report),
+            [
+                ("IHDR".to_string(), DigestStatus::Unchanged),
+                ("PLTE".to_string(), DigestStatus::Added),
+                ("IDAT".to_string(), DigestStatus::Changed),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_untrusted_key() {
+        let mut png = testing_image();
+        seal(&mut png, SealHash::Sha256, &SigningKey::generate()).unwrap();
+        let report = check(&png, &SigningKey::generate().verifying_key()).unwrap();
+        assert!(!report.signature_valid);
+        assert!(!report.is_intact());
+    }
+
+    #[test]
+    fn test_reseal_replaces_seal() {
+        let key = SigningKey::generate();
+        let mut png = testing_image();
+        seal(&mut png, SealHash::Sha256, &key).unwrap();
+        seal(&mut png, SealHash::Blake3, &key).unwrap();
+        assert_eq!(png.chunks_by_type(SEAL_CHUNK_TYPE).count(), 1);
+        assert_eq!(check(&png, &key.verifying_key()).unwrap().hash, SealHash::Blake3);
+    }
+
+    #[test]
+    fn test_unsealed_image() {
+        let key = SigningKey::generate().verifying_key();
+        assert!(matches!(check(&testing_image(), &key), Err(Error::ChunkNotFound { .. })));
+    }
+
+    #[test]
+    fn test_seal_chunk_round_trip() {
+        let seal = Seal::new(&testing_image(), SealHash::Blake3, &SigningKey::generate());
+        let chunk = seal.to_chunk();
+        assert!(!chunk.chunk_type().is_critical());
+        assert!(!chunk.chunk_type().is_safe_to_copy());
+        assert_eq!(Seal::try_from(&chunk).unwrap(), seal);
+
+        let truncated = Chunk::new(chunk.chunk_type().clone(), chunk.data()[..40].to_vec());
+        assert!(matches!(Seal::try_from(&truncated), Err(Error::InvalidSeal { .. })));
+    }
+}
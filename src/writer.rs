@@ -0,0 +1,61 @@
+//! Writing PNG chunks one at a time to any [`Write`] sink.
+
+use std::io::Write;
+
+use crate::chunk::Chunk;
+use crate::png::Png;
+use crate::Result;
+
+/// Writes the PNG signature followed by chunks, without buffering the whole file.
+pub struct ChunkWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    /// Writes the PNG signature.
+    pub fn new(mut writer: W) -> Result<Self> {
+        writer.write_all(&Png::STANDARD_HEADER)?;
+        Ok(ChunkWriter { writer })
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        self.writer.write_all(&chunk.length().to_be_bytes())?;
+        self.writer.write_all(&chunk.chunk_type().bytes())?;
+        self.writer.write_all(chunk.data())?;
+        self.writer.write_all(&chunk.crc().to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_writes_same_bytes_as_png() {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"I am the first chunk".to_vec()),
+            Chunk::new(ChunkType::from_str("LASt").unwrap(), b"I am the last chunk".to_vec()),
+        ];
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        for chunk in &chunks {
+            writer.write_chunk(chunk).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+        assert_eq!(bytes, Png::from_chunks(chunks).as_bytes());
+    }
+
+    #[test]
+    fn test_empty_png_is_only_signature() {
+        let bytes = ChunkWriter::new(Vec::new()).unwrap().finish().unwrap();
+        assert_eq!(bytes, Png::STANDARD_HEADER);
+    }
+}